@@ -0,0 +1,41 @@
+use crate::storage::Storage;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+use std::io;
+
+// Passwords are stored as argon2id PHC strings ($argon2id$v=19$m=...,t=...,p=...$salt$hash), each with its
+// own random salt. Accounts made before hashing still hold plaintext; migrate rehashes them in place.
+
+pub fn hash_password(password: &str) -> io::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+    }
+}
+
+pub fn is_hashed(stored: &str) -> bool {
+    stored.starts_with("$argon2")
+}
+
+pub fn verify_password(stored: &str, password: &str) -> bool {
+    match PasswordHash::new(stored) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Hashes every plaintext password. Returns how many were migrated.
+pub fn migrate(storage: &mut dyn Storage) -> io::Result<usize> {
+    let mut migrated = 0;
+    for account in storage.users()? {
+        if !is_hashed(&account.password) {
+            storage.set_password(account.uuid, &hash_password(&account.password)?)?;
+            migrated += 1;
+        }
+    }
+    Ok(migrated)
+}
@@ -0,0 +1,117 @@
+use crate::memory::Memory;
+use crate::scores::Condition;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Achievements. Each game can have a definitions file, <ACHIEVEMENTS_DIR>/<header title>.cfg, of
+// achievements made of memory conditions in the same form as score definitions' valid lines:
+//
+//   [score_10000]
+//   name = Five digits
+//   description = Score 10,000 points
+//   when = C0A2 >= 01            # ADDR[&MASK] OP VALUE, all hex
+//   when = FFE1&80 != 00         # more when lines must all hold in the same frame
+//
+// An achievement unlocks on the frame its conditions become true, so one that's already true when the
+// game starts (or is loaded) waits until they stop holding and hold again. Unlocks are kept per user in storage.
+
+pub struct Achievement {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub conditions: Vec<Condition>,
+}
+
+pub fn path_for_title(dir: &Path, title: &str) -> PathBuf {
+    let name: String = title.chars().map(|c| if c == '/' || c == '\\' || c == ':' { '_' } else { c }).collect();
+    dir.join(format!("{}.cfg", name))
+}
+
+pub fn parse(text: &str, source: &str) -> Vec<Achievement> {
+    let mut achievements: Vec<Achievement> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(id) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            achievements.push(Achievement { id: id.to_string(), name: id.to_string(), description: String::new(), conditions: Vec::new() });
+            continue;
+        }
+
+        let achievement = match achievements.last_mut() {
+            Some(achievement) => achievement,
+            None => { println!("IGNORING LINE {} OF {} OUTSIDE AN ACHIEVEMENT: {}", number + 1, source, line); continue; },
+        };
+        let parsed = line.split_once('=').and_then(|(field, value)| {
+            let value = value.trim();
+            match field.trim() {
+                "name" => achievement.name = value.to_string(),
+                "description" => achievement.description = value.to_string(),
+                "when" => achievement.conditions.push(Condition::parse(value)?),
+                _ => return None,
+            }
+            Some(())
+        });
+        if parsed.is_none() {
+            println!("IGNORING INVALID ACHIEVEMENT LINE {} OF {}: {}", number + 1, source, line);
+        }
+    }
+
+    // Without conditions an achievement would unlock straight away
+    achievements.retain(|achievement| {
+        if achievement.conditions.is_empty() {
+            println!("IGNORING ACHIEVEMENT {} IN {}, IT HAS NO CONDITIONS", achievement.id, source);
+        }
+        !achievement.conditions.is_empty()
+    });
+    achievements
+}
+
+// No file means no achievements for the game
+pub fn load(path: &Path) -> io::Result<Vec<Achievement>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(parse(&text, &path.display().to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// Checks a game's achievements once per frame
+pub struct Tracker {
+    pub achievements: Vec<Achievement>,
+    pub unlocked: Vec<bool>,
+    was_met: Vec<bool>,
+}
+
+impl Tracker {
+    pub fn new(achievements: Vec<Achievement>, unlocked_ids: &[String]) -> Self {
+        let unlocked = achievements.iter().map(|achievement| unlocked_ids.contains(&achievement.id)).collect();
+        let was_met = vec![true; achievements.len()];
+        Tracker { achievements, unlocked, was_met }
+    }
+
+    // Returns the achievements unlocked this frame
+    pub fn check(&mut self, memory: &Memory) -> Vec<&Achievement> {
+        let mut newly_unlocked = Vec::new();
+        for (index, achievement) in self.achievements.iter().enumerate() {
+            if self.unlocked[index] {
+                continue;
+            }
+            let met = achievement.conditions.iter().all(|condition| condition.holds(memory));
+            if met && !self.was_met[index] {
+                self.unlocked[index] = true;
+                newly_unlocked.push(achievement);
+            }
+            self.was_met[index] = met;
+        }
+        newly_unlocked
+    }
+
+    // After loading a state (rewind, movies) memory jumps, so wait for conditions to be reached again
+    pub fn reset(&mut self) {
+        self.was_met.iter_mut().for_each(|met| *met = true);
+    }
+}
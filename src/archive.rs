@@ -0,0 +1,329 @@
+use crate::crc32::crc32;
+
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Roms kept in .zip and .gz archives. Both hold raw deflate streams, so the containers are read by hand
+// and miniz_oxide does the inflating. A zip gives its first .gb or .gbc entry; a gzip its only member,
+// which should be named like a rom (game.gb.gz, or a .gz whose stored name ends in .gb/.gbc).
+
+// The biggest licensed cartridges are 8 MiB, anything past that is a broken or hostile archive
+pub const MAX_ROM_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn is_rom_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.eq_ignore_ascii_case("zip") || extension.eq_ignore_ascii_case("gz"),
+        None => false,
+    }
+}
+
+// The whole rom, unpacked if the file is an archive
+pub fn read_rom(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase()).as_deref() {
+        Some("zip") => unzip_rom(&data),
+        Some("gz") => gunzip_rom(&data, path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string())),
+        _ => Ok(data),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> io::Result<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).ok_or_else(|| invalid("truncated archive"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok_or_else(|| invalid("truncated archive"))
+}
+
+fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    decompress_to_vec_with_limit(data, MAX_ROM_SIZE).map_err(|e| invalid(&format!("bad deflate stream ({:?})", e.status)))
+}
+
+fn check_crc(rom: &[u8], expected: u32) -> io::Result<()> {
+    if crc32(rom) != expected {
+        return Err(invalid("archive crc doesn't match, the file is damaged"));
+    }
+    Ok(())
+}
+
+/////////////////////////////////// ZIP ///////////////////////////////////
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4B50;
+const CENTRAL_DIRECTORY_ENTRY: u32 = 0x0201_4B50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4B50;
+
+// The central directory lists every entry; it's found through the end record, which sits before an
+// optional comment of up to 64 KiB at the very end of the file
+fn unzip_rom(data: &[u8]) -> io::Result<Vec<u8>> {
+    let search_from = data.len().saturating_sub(22 + 0xFFFF);
+    let end = (search_from..data.len().saturating_sub(21)).rev()
+        .find(|&offset| u32_at(data, offset).ok() == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("not a zip file"))?;
+    let entries = u16_at(data, end + 10)?;
+    let mut offset = u32_at(data, end + 16)? as usize;
+
+    for _ in 0..entries {
+        if u32_at(data, offset)? != CENTRAL_DIRECTORY_ENTRY {
+            return Err(invalid("bad zip central directory"));
+        }
+        let flags = u16_at(data, offset + 8)?;
+        let method = u16_at(data, offset + 10)?;
+        let crc = u32_at(data, offset + 16)?;
+        let compressed_size = u32_at(data, offset + 20)? as usize;
+        let name_length = u16_at(data, offset + 28)? as usize;
+        let extra_length = u16_at(data, offset + 30)? as usize;
+        let comment_length = u16_at(data, offset + 32)? as usize;
+        let local_header = u32_at(data, offset + 42)? as usize;
+        let name = data.get(offset + 46..offset + 46 + name_length).ok_or_else(|| invalid("truncated archive"))?;
+        let name = String::from_utf8_lossy(name);
+        offset += 46 + name_length + extra_length + comment_length;
+
+        if !is_rom_name(&name) {
+            continue;
+        }
+        if flags & 1 != 0 {
+            return Err(invalid("encrypted zip entries aren't supported"));
+        }
+        if compressed_size == 0xFFFF_FFFF || local_header == 0xFFFF_FFFF {
+            return Err(invalid("zip64 archives aren't supported"));
+        }
+
+        // The local header repeats the name and can have its own extra field
+        if u32_at(data, local_header)? != LOCAL_FILE_HEADER {
+            return Err(invalid("bad zip local header"));
+        }
+        let start = local_header + 30 + u16_at(data, local_header + 26)? as usize + u16_at(data, local_header + 28)? as usize;
+        let stored = data.get(start..start + compressed_size).ok_or_else(|| invalid("truncated archive"))?;
+        let rom = match method {
+            0 => stored.to_vec(),
+            8 => inflate(stored)?,
+            _ => return Err(invalid(&format!("zip compression method {} isn't supported", method))),
+        };
+        check_crc(&rom, crc)?;
+        return Ok(rom);
+    }
+    Err(invalid("no .gb or .gbc file in the zip"))
+}
+
+/////////////////////////////////// GZIP ///////////////////////////////////
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+fn gunzip_rom(data: &[u8], stem: String) -> io::Result<Vec<u8>> {
+    if data.len() < 18 || data[0] != 0x1F || data[1] != 0x8B {
+        return Err(invalid("not a gzip file"));
+    }
+    if data[2] != 8 {
+        return Err(invalid("gzip compression method isn't deflate"));
+    }
+    let flags = data[3];
+    let mut offset = 10;
+    if flags & FEXTRA != 0 {
+        offset += 2 + u16_at(data, offset)? as usize;
+    }
+    let mut name = stem;
+    for (flag, is_name) in [(FNAME, true), (FCOMMENT, false)] {
+        if flags & flag != 0 {
+            let length = data.get(offset..).and_then(|rest| rest.iter().position(|&byte| byte == 0)).ok_or_else(|| invalid("truncated archive"))?;
+            if is_name {
+                name = String::from_utf8_lossy(&data[offset..offset + length]).to_string();
+            }
+            offset += length + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        offset += 2;
+    }
+    if !is_rom_name(&name) {
+        return Err(invalid("the gzip doesn't hold a .gb or .gbc file"));
+    }
+
+    // The trailer is the crc and size of the unpacked data
+    let trailer = data.len() - 8;
+    let stream = data.get(offset..trailer).ok_or_else(|| invalid("truncated archive"))?;
+    let rom = inflate(stream)?;
+    check_crc(&rom, u32_at(data, trailer)?)?;
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+
+    const ROM: &[u8] = b"a tiny rom, repeated a few times. a tiny rom, repeated a few times.";
+
+    struct Entry {
+        name: String,
+        method: u16,
+        data: Vec<u8>,
+        crc: u32,
+    }
+
+    fn stored(name: &str, data: &[u8]) -> Entry {
+        Entry { name: name.to_string(), method: 0, data: data.to_vec(), crc: crc32(data) }
+    }
+
+    fn deflated(name: &str, data: &[u8]) -> Entry {
+        Entry { name: name.to_string(), method: 8, data: compress_to_vec(data, 6), crc: crc32(data) }
+    }
+
+    fn zip(entries: &[Entry]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+        for entry in entries {
+            let fields = |data: &mut Vec<u8>| {
+                data.extend_from_slice(&[0, 0]); // flags
+                data.extend_from_slice(&entry.method.to_le_bytes());
+                data.extend_from_slice(&[0; 4]); // time, date
+                data.extend_from_slice(&entry.crc.to_le_bytes());
+                data.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+                data.extend_from_slice(&[0; 4]); // unpacked size, unused
+                data.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+                data.extend_from_slice(&[0; 2]); // extra field length
+            };
+            directory.extend_from_slice(&CENTRAL_DIRECTORY_ENTRY.to_le_bytes());
+            directory.extend_from_slice(&[20, 0, 20, 0]);
+            fields(&mut directory);
+            directory.extend_from_slice(&[0; 10]); // comment length, disk, attributes
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+
+            data.extend_from_slice(&LOCAL_FILE_HEADER.to_le_bytes());
+            data.extend_from_slice(&[20, 0]);
+            fields(&mut data);
+            data.extend_from_slice(entry.name.as_bytes());
+            data.extend_from_slice(&entry.data);
+        }
+        let directory_offset = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&directory_offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    fn gzip(name: Option<&str>, rom: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x1F, 0x8B, 8, if name.is_some() { FNAME } else { 0 }, 0, 0, 0, 0, 0, 255];
+        if let Some(name) = name {
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+        }
+        data.extend_from_slice(&compress_to_vec(rom, 6));
+        data.extend_from_slice(&crc32(rom).to_le_bytes());
+        data.extend_from_slice(&(rom.len() as u32).to_le_bytes());
+        data
+    }
+
+    fn message(result: io::Result<Vec<u8>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn unzips_stored_and_deflated_roms() {
+        assert_eq!(unzip_rom(&zip(&[stored("game.gb", ROM)])).unwrap(), ROM);
+        assert_eq!(unzip_rom(&zip(&[stored("readme.txt", b"hi"), deflated("Game.GBC", ROM)])).unwrap(), ROM);
+    }
+
+    #[test]
+    fn zip_without_a_rom() {
+        assert_eq!(message(unzip_rom(&zip(&[stored("readme.txt", b"hi")]))), "no .gb or .gbc file in the zip");
+        assert_eq!(message(unzip_rom(&zip(&[]))), "no .gb or .gbc file in the zip");
+    }
+
+    #[test]
+    fn rejects_broken_zips() {
+        assert_eq!(message(unzip_rom(b"")), "not a zip file");
+        assert_eq!(message(unzip_rom(ROM)), "not a zip file");
+
+        let mut damaged = stored("game.gb", ROM);
+        damaged.crc ^= 1;
+        assert_eq!(message(unzip_rom(&zip(&[damaged]))), "archive crc doesn't match, the file is damaged");
+
+        let mut corrupt = deflated("game.gb", ROM);
+        corrupt.data.iter_mut().for_each(|byte| *byte = !*byte);
+        assert!(unzip_rom(&zip(&[corrupt])).is_err());
+
+        let mut unsupported = stored("game.gb", ROM);
+        unsupported.method = 14;
+        assert_eq!(message(unzip_rom(&zip(&[unsupported]))), "zip compression method 14 isn't supported");
+    }
+
+    #[test]
+    fn rejects_truncated_zips() {
+        let data = zip(&[stored("game.gb", ROM)]);
+        for length in 0..data.len() {
+            assert!(unzip_rom(&data[..length]).is_err());
+        }
+        // An intact end record pointing past the end of the file
+        let mut data = zip(&[stored("game.gb", ROM)]);
+        let end = data.len() - 22;
+        data[end + 16..end + 20].copy_from_slice(&0xFFFF_FF00_u32.to_le_bytes());
+        assert_eq!(message(unzip_rom(&data)), "truncated archive");
+        // And an entry bigger than the file
+        let mut data = zip(&[stored("game.gb", ROM)]);
+        let directory = 30 + "game.gb".len() + ROM.len();
+        data[directory + 20..directory + 24].copy_from_slice(&0x00FF_FF00_u32.to_le_bytes());
+        assert_eq!(message(unzip_rom(&data)), "truncated archive");
+    }
+
+    #[test]
+    fn gunzips_roms() {
+        assert_eq!(gunzip_rom(&gzip(None, ROM), String::from("game.gb")).unwrap(), ROM);
+        assert_eq!(gunzip_rom(&gzip(Some("game.gbc"), ROM), String::from("renamed")).unwrap(), ROM);
+    }
+
+    #[test]
+    fn gzip_needs_a_rom_name() {
+        assert_eq!(message(gunzip_rom(&gzip(None, ROM), String::from("game"))), "the gzip doesn't hold a .gb or .gbc file");
+        assert_eq!(message(gunzip_rom(&gzip(Some("notes.txt"), ROM), String::from("game.gb"))), "the gzip doesn't hold a .gb or .gbc file");
+    }
+
+    #[test]
+    fn rejects_broken_gzips() {
+        assert_eq!(message(gunzip_rom(b"", String::from("game.gb"))), "not a gzip file");
+        assert_eq!(message(gunzip_rom(&zip(&[stored("game.gb", ROM)]), String::from("game.gb"))), "not a gzip file");
+
+        let mut damaged = gzip(None, ROM);
+        let crc = damaged.len() - 8;
+        damaged[crc] ^= 1;
+        assert_eq!(message(gunzip_rom(&damaged, String::from("game.gb"))), "archive crc doesn't match, the file is damaged");
+
+        let mut corrupt = gzip(None, ROM);
+        let trailer = corrupt.len() - 8;
+        corrupt[10..trailer].iter_mut().for_each(|byte| *byte = !*byte);
+        assert!(gunzip_rom(&corrupt, String::from("game.gb")).is_err());
+
+        // A name that never ends
+        let mut unterminated = vec![0x1F, 0x8B, 8, FNAME, 0, 0, 0, 0, 0, 255];
+        unterminated.extend_from_slice(b"game.gb and then some more");
+        assert_eq!(message(gunzip_rom(&unterminated, String::new())), "truncated archive");
+    }
+
+    #[test]
+    fn rejects_truncated_gzips() {
+        let data = gzip(Some("game.gb"), ROM);
+        for length in 0..data.len() {
+            assert!(gunzip_rom(&data[..length], String::from("game.gb")).is_err());
+        }
+    }
+}
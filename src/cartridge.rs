@@ -0,0 +1,436 @@
+use crate::patch::read_patched_rom;
+
+use std::io;
+use std::path::Path;
+
+// The cartridge header, 0x0100-0x014F of the rom
+
+pub const HEADER_SIZE: usize = 0x150;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mbc {
+    None,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+    Unknown,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CgbSupport {
+    None,
+    Enhanced, // works on a DMG too
+    Only,
+}
+
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub licensee: String,
+    pub cartridge_type: u8,
+    pub mbc: Mbc,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub rom_size: usize, // bytes
+    pub ram_size: usize, // bytes, not counting MBC2's built in 512 nibbles
+    pub destination_code: u8, // 0x00 Japan (and possibly overseas), 0x01 overseas only
+    pub version: u8,
+    pub header_checksum: u8,
+    pub computed_header_checksum: u8,
+    pub global_checksum: u16,
+    pub computed_global_checksum: Option<u16>, // None when only the header was parsed
+}
+
+impl CartridgeHeader {
+    // Parses the header from the start of a rom. With the whole rom the global checksum is computed too.
+    pub fn parse(rom: &[u8], whole_rom: bool) -> io::Result<Self> {
+        if rom.len() < HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} bytes is too short for a cartridge header", rom.len())));
+        }
+
+        let cgb = match rom[0x143] {
+            0x80 => CgbSupport::Enhanced,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+        // CGB carts use the last byte of the title for their flag
+        let title_bytes = if cgb == CgbSupport::None { &rom[0x134..=0x143] } else { &rom[0x134..0x143] };
+        let title_end = title_bytes.iter().position(|&byte| byte == 0).unwrap_or(title_bytes.len());
+        let title: String = String::from_utf8_lossy(&title_bytes[..title_end]).chars().filter(|c| !c.is_control()).collect();
+
+        let licensee = if rom[0x14B] != 0x33 {
+            match_old_licensee_code(rom[0x14B])
+        } else {
+            match_new_licensee_code(&String::from_utf8_lossy(&rom[0x144..=0x145]))
+        };
+
+        let cartridge_type = rom[0x147];
+        let (mbc, ram, battery, timer, rumble) = match cartridge_type {
+            0x00 => (Mbc::None, false, false, false, false),
+            0x01 => (Mbc::Mbc1, false, false, false, false),
+            0x02 => (Mbc::Mbc1, true, false, false, false),
+            0x03 => (Mbc::Mbc1, true, true, false, false),
+            0x05 => (Mbc::Mbc2, false, false, false, false),
+            0x06 => (Mbc::Mbc2, false, true, false, false),
+            0x08 => (Mbc::None, true, false, false, false),
+            0x09 => (Mbc::None, true, true, false, false),
+            0x0B => (Mbc::Mmm01, false, false, false, false),
+            0x0C => (Mbc::Mmm01, true, false, false, false),
+            0x0D => (Mbc::Mmm01, true, true, false, false),
+            0x0F => (Mbc::Mbc3, false, true, true, false),
+            0x10 => (Mbc::Mbc3, true, true, true, false),
+            0x11 => (Mbc::Mbc3, false, false, false, false),
+            0x12 => (Mbc::Mbc3, true, false, false, false),
+            0x13 => (Mbc::Mbc3, true, true, false, false),
+            0x19 => (Mbc::Mbc5, false, false, false, false),
+            0x1A => (Mbc::Mbc5, true, false, false, false),
+            0x1B => (Mbc::Mbc5, true, true, false, false),
+            0x1C => (Mbc::Mbc5, false, false, false, true),
+            0x1D => (Mbc::Mbc5, true, false, false, true),
+            0x1E => (Mbc::Mbc5, true, true, false, true),
+            0x20 => (Mbc::Mbc6, false, false, false, false),
+            0x22 => (Mbc::Mbc7, true, true, false, true),
+            0xFC => (Mbc::PocketCamera, false, false, false, false),
+            0xFD => (Mbc::Tama5, false, false, false, false),
+            0xFE => (Mbc::HuC3, false, false, false, false),
+            0xFF => (Mbc::HuC1, true, true, false, false),
+            _ => (Mbc::Unknown, false, false, false, false),
+        };
+
+        let ram_size = match rom[0x149] {
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            _ => 0,
+        };
+
+        let computed_header_checksum = rom[0x134..=0x14C].iter().fold(0_u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+        // Every byte of the rom except the checksum itself
+        let computed_global_checksum = if whole_rom {
+            Some(rom.iter().enumerate().filter(|(address, _)| *address != 0x14E && *address != 0x14F)
+                .fold(0_u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16)))
+        } else { None };
+
+        Ok(CartridgeHeader {
+            title,
+            cgb,
+            sgb: rom[0x146] == 0x03,
+            licensee,
+            cartridge_type,
+            mbc,
+            ram,
+            battery,
+            timer,
+            rumble,
+            rom_size: (32 * 1024) << rom[0x148].min(8),
+            ram_size,
+            destination_code: rom[0x14A],
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            computed_header_checksum,
+            global_checksum: (rom[0x14E] as u16) << 8 | rom[0x14F] as u16,
+            computed_global_checksum,
+        })
+    }
+
+    // Reads the whole rom as it's played (unpacked and patched) so the global checksum can be verified
+    pub fn load(filename: &str) -> io::Result<Self> {
+        CartridgeHeader::parse(&read_patched_rom(Path::new(filename))?, true)
+    }
+
+    // The boot rom refuses to start a cart whose header checksum is wrong
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    // Nothing checks the global checksum, so a mismatch usually means a hacked or damaged rom
+    pub fn global_checksum_valid(&self) -> Option<bool> {
+        self.computed_global_checksum.map(|checksum| checksum == self.global_checksum)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM 1",
+            0x09 => "ROM+RAM+BATTERY 1",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY 2",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM 2",
+            0x13 => "MBC3+RAM+BATTERY 2",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "None",
+        }
+    }
+
+    pub fn destination(&self) -> &'static str {
+        match self.destination_code {
+            0x00 => "Japan",
+            0x01 => "Overseas only",
+            _ => "None",
+        }
+    }
+}
+
+// Sizes as shown in the TUI, 32KiB, 1MiB
+pub fn format_size(bytes: usize) -> String {
+    match bytes {
+        0 => "None".to_string(),
+        bytes if bytes >= 1024 * 1024 => format!("{}MiB", bytes / (1024 * 1024)),
+        bytes => format!("{}KiB", bytes / 1024),
+    }
+}
+
+pub fn match_old_licensee_code(code: u8) -> String {
+    let licensee = match code {
+        0x00 => "None",
+        0x01 => "Nintendo",
+        0x08 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C => "Elite Systems",
+        0x13 => "EA (Electronic Arts)",
+        0x18 => "Hudsonsoft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F => "Virgin Interactive",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 => "Infogrames",
+        0x31 => "Nintendo",
+        0x32 => "Bandai",
+        0x33 => "Indicates that the New licensee code should be used instead.",
+        0x34 => "Konami",
+        0x35 => "HectorSoft",
+        0x38 => "Capcom",
+        0x39 => "Banpresto",
+        0x3C => ".Entertainment i",
+        0x3E => "Gremlin",
+        0x41 => "Ubisoft",
+        0x42 => "Atlus",
+        0x44 => "Malibu",
+        0x46 => "Angel",
+        0x47 => "Spectrum Holoby",
+        0x49 => "Irem",
+        0x4A => "Virgin Interactive",
+        0x4D => "Malibu",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "American Sammy",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus",
+        0x61 => "Virgin Interactive",
+        0x67 => "Ocean Interactive",
+        0x69 => "EA (Electronic Arts)",
+        0x6E => "Elite Systems",
+        0x6F => "Electro Brain",
+        0x70 => "Infogrames",
+        0x71 => "Interplay",
+        0x72 => "Broderbund",
+        0x73 => "Sculptered Soft",
+        0x75 => "The Sales Curve",
+        0x78 => "t.hq",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "Microprose",
+        0x7F => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "Lozc",
+        0x86 => "Tokuma Shoten Intermedia",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai",
+        0x8E => "Ape",
+        0x8F => "I’Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions Co.",
+        0x95 => "Varie Corporation",
+        0x96 => "Yonezawa/S’Pal",
+        0x97 => "Kaneko",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9D => "Banpresto",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA2 => "Bandai",
+        0xA4 => "Konami",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAA => "Broderbund",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB0 => "acclaim",
+        0xB1 => "ASCII or Nexsoft",
+        0xB2 => "Bandai",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy",
+        0xC0 => "Taito",
+        0xC2 => "Kemco",
+        0xC3 => "Squaresoft",
+        0xC4 => "Tokuma Shoten Intermedia",
+        0xC5 => "Data East",
+        0xC6 => "Tonkinhouse",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra",
+        0xCB => "Vap",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xCE => ".Pony Canyon or",
+        0xCF => "Angel",
+        0xD0 => "Taito",
+        0xD1 => "Sofel",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD6 => "Naxat Soft",
+        0xD7 => "Copya System",
+        0xD9 => "Banpresto",
+        0xDA => "Tomy",
+        0xDB => "LJN",
+        0xDD => "NCS",
+        0xDE => "Human",
+        0xDF => "Altron",
+        0xE0 => "Jaleco",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE3 => "Varie",
+        0xE5 => "Epcoh",
+        0xE7 => "Athena",
+        0xE8 => "Asmik ACE Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEB => "Atlus",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        0xFF => "LJN",
+        _ => "None",
+    };
+    licensee.to_string()
+}
+
+pub fn match_new_licensee_code(code: &str) -> String {
+    let licensee = match code {
+        "00" => "None",
+        "01" => "Nintendo R&D1",
+        "08" => "Capcom",
+        "13" => "Electronic Arts",
+        "18" => "Hudson Soft",
+        "19" => "b-ai",
+        "20" => "kss",
+        "22" => "pow",
+        "24" => "PCM Complete",
+        "25" => "san-x",
+        "28" => "Kemco Japan",
+        "29" => "seta",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean/Acclaim",
+        "34" => "Konami",
+        "35" => "Hector",
+        "37" => "Taito",
+        "38" => "Hudson",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu",
+        "46" => "angel",
+        "47" => "Bullet-Proof",
+        "49" => "irem",
+        "50" => "Absolute",
+        "51" => "Acclaim",
+        "52" => "Activision",
+        "53" => "American sammy",
+        "54" => "Konami",
+        "55" => "Hi tech entertainment",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus",
+        "61" => "Virgin",
+        "64" => "LucasArts",
+        "67" => "Ocean",
+        "69" => "Electronic Arts",
+        "70" => "Infogrames",
+        "71" => "Interplay",
+        "72" => "Broderbund",
+        "73" => "sculptured",
+        "75" => "sci",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "misawa",
+        "83" => "lozc",
+        "86" => "Tokuma Shoten Intermedia",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video system",
+        "93" => "Ocean/Acclaim",
+        "95" => "Varie",
+        "96" => "Yonezawa/s’pal",
+        "97" => "Kaneko",
+        "99" => "Pack in soft",
+        "9H" => "Bottom Up",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        _ => "None",
+    };
+    licensee.to_string()
+}
@@ -0,0 +1,142 @@
+use crate::palette::Palette;
+
+// Command line parsing. With no rom the TUI library is shown as before, with one it is launched directly.
+// Options take their value either as the next argument or after an =, e.g. --scale 4 or --scale=4.
+
+pub const USAGE: &str = "\
+USAGE:
+    nemulator [OPTIONS] [ROM]
+    nemulator disasm <ROM> [BANK]
+    nemulator gdb <ROM> [PORT]
+
+OPTIONS:
+    --palette <NAME>       grayscale, redscale, bluescale, greenscale or a PALETTE_DIR file
+    --scale <N>            window scale (default 3)
+    --boot-rom <FILE>      run a 256 byte DMG boot rom before the cartridge
+    --headless             run without a window, needs a ROM and --frames
+    --frames <N>           stop after N frames
+    --screenshot <FILE>    save the last frame as a PNG on exit
+    --trace                write a trace of every instruction to trace.log
+    --no-db                don't load accounts or save scores, play as a guest
+    --record <FILE>        record an input movie from power on
+    --play <FILE>          play an input movie back
+    -h, --help             show this message";
+
+pub enum Command {
+    Run(Options),
+    Disasm { rom: String, bank: usize },
+    Gdb { rom: String, port: u16 },
+    Help,
+}
+
+pub struct Options {
+    pub rom: Option<String>,
+    pub palette: Option<Palette>,
+    pub scale: u32,
+    pub boot_rom: Option<String>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub screenshot: Option<String>,
+    pub trace: bool,
+    pub no_db: bool,
+    pub record: Option<String>,
+    pub play: Option<String>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options {
+            rom: None,
+            palette: None,
+            scale: 3,
+            boot_rom: None,
+            headless: false,
+            frames: None,
+            screenshot: None,
+            trace: false,
+            no_db: false,
+            record: None,
+            play: None,
+        }
+    }
+}
+
+// args excludes the program name
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("disasm") => {
+            let rom = args.get(1).ok_or("disasm needs a ROM")?.clone();
+            let bank = match args.get(2) {
+                Some(bank) => bank.parse().map_err(|_| format!("invalid bank: {}", bank))?,
+                None => 0,
+            };
+            return Ok(Command::Disasm { rom, bank });
+        },
+        Some("gdb") => {
+            let rom = args.get(1).ok_or("gdb needs a ROM")?.clone();
+            let port = match args.get(2) {
+                Some(port) => port.parse().map_err(|_| format!("invalid port: {}", port))?,
+                None => 2345,
+            };
+            return Ok(Command::Gdb { rom, port });
+        },
+        _ => {},
+    }
+
+    let mut options = Options::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            match inline_value.clone() {
+                Some(value) => Ok(value),
+                None => args.next().cloned().ok_or(format!("{} needs a value", flag)),
+            }
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--palette" => {
+                let name = value()?;
+                options.palette = Some(Palette::from_name(&name).ok_or(format!("unknown palette: {}", name))?);
+            },
+            "--scale" => {
+                let scale = value()?;
+                options.scale = match scale.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("invalid scale: {}", scale)),
+                };
+            },
+            "--boot-rom" => options.boot_rom = Some(value()?),
+            "--headless" => options.headless = true,
+            "--frames" => {
+                let frames = value()?;
+                options.frames = Some(frames.parse().map_err(|_| format!("invalid frame count: {}", frames))?);
+            },
+            "--screenshot" => options.screenshot = Some(value()?),
+            "--trace" => options.trace = true,
+            "--no-db" => options.no_db = true,
+            "--record" => options.record = Some(value()?),
+            "--play" => options.play = Some(value()?),
+            _ if flag.starts_with('-') => return Err(format!("unknown option: {}", flag)),
+            _ if options.rom.is_none() => options.rom = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    if options.headless && (options.rom.is_none() || options.frames.is_none()) {
+        return Err("--headless needs a ROM and --frames".to_string());
+    }
+    if (options.record.is_some() || options.play.is_some()) && options.rom.is_none() {
+        return Err("--record and --play need a ROM".to_string());
+    }
+    if options.headless && options.record.is_some() {
+        return Err("--record needs a window to take input from".to_string());
+    }
+    if options.record.is_some() && options.play.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+    Ok(Command::Run(options))
+}
@@ -1,8 +1,12 @@
 use crate::memory::Memory;
 use crate::registers::*;
 use crate::ppu::*;
+use crate::palette::Palette;
 use crate::timer::*;
 use crate::apu::*;
+use crate::disassembler;
+use crate::symbols::{self, SymbolTable};
+use crate::savestate::{StateReader, StateWriter};
 
 use std::borrow::BorrowMut;
 use std::{thread, time};
@@ -90,6 +94,27 @@ impl BinaryHeap {
         }
     }
 
+    // Interrupts are stored by priority, in heap order, so the tree loads back unchanged
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.nodes.len() as u8);
+        for int in self.nodes.iter() {
+            state.u8(Self::get_interrupt_priority(*int) as u8);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) {
+        self.nodes.clear();
+        for _ in 0..state.u8() {
+            self.nodes.push(match state.u8() {
+                4 => Interrupt::VBlank,
+                3 => Interrupt::STAT,
+                2 => Interrupt::Timer,
+                1 => Interrupt::Serial,
+                _ => Interrupt::Joypad,
+            });
+        }
+    }
+
     fn shift_down(&mut self, i: usize, len: usize) {
         let left_child = Self::left_child(i);
         let right_child = Self::right_child(i);
@@ -129,7 +154,7 @@ pub struct InputStates {
     pub a: bool,
 
     pub input_irq: bool,
-    pub last_states: u8,
+    pub last_states: u8, // the low nibble of P1 when last checked
 }
 
 impl InputStates {
@@ -150,30 +175,55 @@ impl InputStates {
         }
     }
 
-    pub fn get_states(&mut self, joyp: u8) -> u8 { // joyp = p1 before, !states = p1 after, check for bit high to low and send off irq on change
-        if joyp & 0b0010_0000 != 0 && joyp & 0b0001_0000 == 0 { // Dpad selected
-            let states = 0b0001_0000 | (self.down as u8) << 3 | (self.up as u8) << 2 | (self.left as u8) << 1 | (self.right as u8); 
-           /* for bit in 0..=3 {
-                if (states >> bit) & 1 > (self.last_states >> bit) & 1 {
-                    self.input_irq = true;
-                    println!("{:#010b}, {:#010b}", !states, self.last_states);
-                    break;
-                }
-            }*/
-            self.last_states = !states;
-            !states
-        } else if joyp & 0b0010_0000 == 0 && joyp & 0b0001_0000 != 0 {
-            let states = 0b0010_0000 | (self.start as u8) << 3 | (self.select as u8) << 2 | (self.b as u8) << 1 | (self.a as u8); 
-            /*for bit in 0..=3 {
-                if (!states >> bit) & 1 > (self.last_states >> bit) & 1 {
-                    self.input_irq = true;
-                    println!("{:#010b}, {:#010b}", !states, self.last_states);
-                    break;
-                }
-            }*/
-            self.last_states = !states;
-            !states
-        } else { 0xFF }
+    // Only the joypad register's side is saved, the buttons themselves are whatever the host is holding
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.input_irq);
+        state.u8(self.last_states);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) {
+        self.input_irq = state.bool();
+        self.last_states = state.u8();
+    }
+
+    // One bit per button, down in bit 7 through to a in bit 0 (JOYP order)
+    pub fn pack(&self) -> u8 {
+        (self.down as u8) << 7 | (self.up as u8) << 6 | (self.left as u8) << 5 | (self.right as u8) << 4
+        | (self.start as u8) << 3 | (self.select as u8) << 2 | (self.b as u8) << 1 | (self.a as u8)
+    }
+
+    pub fn unpack(&mut self, packed: u8) {
+        self.down = packed & 0b1000_0000 != 0;
+        self.up = packed & 0b0100_0000 != 0;
+        self.left = packed & 0b0010_0000 != 0;
+        self.right = packed & 0b0001_0000 != 0;
+        self.start = packed & 0b0000_1000 != 0;
+        self.select = packed & 0b0000_0100 != 0;
+        self.b = packed & 0b0000_0010 != 0;
+        self.a = packed & 0b0000_0001 != 0;
+    }
+
+    // P1 as the cpu reads it. Bits 4 and 5 select the d-pad and the buttons respectively when low,
+    // and a pressed button pulls its line low. With both groups selected the lines are shared, so it's the AND of the two.
+    pub fn joyp(&self, select: u8) -> u8 {
+        let mut lines = 0x0F;
+        if select & 0b0001_0000 == 0 {
+            lines &= !((self.down as u8) << 3 | (self.up as u8) << 2 | (self.left as u8) << 1 | (self.right as u8));
+        }
+        if select & 0b0010_0000 == 0 {
+            lines &= !((self.start as u8) << 3 | (self.select as u8) << 2 | (self.b as u8) << 1 | (self.a as u8));
+        }
+        0b1100_0000 | (select & 0b0011_0000) | lines
+    }
+
+    // Raises the joypad interrupt when any of the 4 input lines goes from high to low,
+    // whether that's from a button being pressed or from the game selecting a group with a button held
+    pub fn update(&mut self, select: u8) {
+        let lines = self.joyp(select) & 0x0F;
+        if self.last_states & !lines & 0x0F != 0 {
+            self.input_irq = true;
+        }
+        self.last_states = lines;
     }
 }
 
@@ -273,6 +323,7 @@ const KIB:usize = 1024;
 
 pub struct CPU {
     pub halted: bool,
+    pub stopped: bool,
     pub halt_bug_with_enter: bool,
     pub halt_bug_without_enter: bool,
     pub ime: bool,
@@ -283,6 +334,7 @@ pub struct CPU {
     pub sp: u16,
 
     pub t_cycles: u16,
+    pub total_cycles: u64, // t-cycles since power on, never wraps - used for pacing
     pub timer: Timer,
 
     pub ppu: PPU,
@@ -294,9 +346,10 @@ pub struct CPU {
 }
 
 impl CPU {
-    pub fn new(selected_palette: Palette) -> Self {
+    pub fn new(selected_palette: Palette, renderer: Option<SDLRenderer>) -> Self {
         CPU {
             halted: false,
+            stopped: false,
             halt_bug_with_enter: false,
             halt_bug_without_enter: false,
             ime: false,
@@ -307,9 +360,10 @@ impl CPU {
             sp: 0xFFFE,
 
             t_cycles: 0,
+            total_cycles: 0,
             timer: Timer::new(),
 
-            ppu: PPU::new(selected_palette),
+            ppu: PPU::new(selected_palette, renderer),
 
             interrupt_queue: BinaryHeap::new(),
             interrupt_queue_bitflags: 0,
@@ -317,6 +371,13 @@ impl CPU {
         }
     }
 
+    // With a boot rom loaded, start from 0000 with everything zeroed and let it set the machine up
+    pub fn start_boot_rom(&mut self) {
+        self.pc = 0;
+        self.sp = 0;
+        self.registers = Registers { A: 0, F: 0, B: 0, C: 0, D: 0, E: 0, H: 0, L: 0 };
+    }
+
     pub fn mock_boot_rom(&mut self) {
         self.memory.write(0xFF00, 0xCF);
         self.memory.write(0xFF02, 0x7E);
@@ -359,18 +420,111 @@ impl CPU {
         self.memory.write(0xFF70, 0xFF);
     }
 
-    pub fn get_game_score(&mut self, guid: i32) -> i32 {
-        let score = match guid {
-            1 => { 0 }, // Not supported
-            2 | 3=> { // Tetris AND Dr Mario -> Score in same place
-                let top = self.memory.read(0xC0A0); //.to_string();
-                let middle = self.memory.read(0xC0A1); //.to_string();
-                let bottom = self.memory.read(0xC0A2); // .to_string();
-                (format!("{:x}", bottom) + &format!("{:x}", middle) + &format!("{:x}", top)).parse().unwrap()
-            },
-            _ => { unreachable!() },
+    // Snapshot of the whole machine, minus the cartridge rom. total_cycles is left out so that
+    // loading an older state doesn't send the frame pacer's clock backwards.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.halted);
+        state.bool(self.stopped);
+        state.bool(self.halt_bug_with_enter);
+        state.bool(self.halt_bug_without_enter);
+        state.bool(self.ime);
+        state.bool(self.ime_waiting);
+        let r = &self.registers;
+        state.bytes(&[r.A, r.F, r.B, r.C, r.D, r.E, r.H, r.L]);
+        state.u16(self.pc);
+        state.u16(self.sp);
+        state.u16(self.t_cycles);
+
+        self.memory.save_state(&mut state);
+        self.timer.save_state(&mut state);
+        self.ppu.save_state(&mut state);
+        self.interrupt_queue.save_state(&mut state);
+        state.u8(self.interrupt_queue_bitflags);
+        self.input_states.save_state(&mut state);
+        state.data
+    }
+
+    // Returns false (leaving the cpu untouched) if the state was written by a different version or is damaged.
+    // A state's length depends on what it holds, so a truncated one is only found by reading it; the cpu is
+    // put back from a state of its own when that happens.
+    pub fn load_state(&mut self, data: &[u8]) -> bool {
+        let mut state = match StateReader::new(data) {
+            Some(state) => state,
+            None => return false,
         };
-        score
+        let backup = self.save_state();
+        self.halted = state.bool();
+        self.stopped = state.bool();
+        self.halt_bug_with_enter = state.bool();
+        self.halt_bug_without_enter = state.bool();
+        self.ime = state.bool();
+        self.ime_waiting = state.bool();
+        let mut r = [0_u8; 8];
+        state.bytes(&mut r);
+        self.registers = Registers { A: r[0], F: r[1], B: r[2], C: r[3], D: r[4], E: r[5], H: r[6], L: r[7] };
+        self.pc = state.u16();
+        self.sp = state.u16();
+        self.t_cycles = state.u16();
+
+        self.memory.load_state(&mut state);
+        self.timer.load_state(&mut state);
+        self.ppu.load_state(&mut state);
+        self.interrupt_queue.load_state(&mut state);
+        self.interrupt_queue_bitflags = state.u8();
+        self.input_states.load_state(&mut state);
+        if !state.complete() {
+            self.load_state(&backup);
+            return false;
+        }
+        true
+    }
+
+    // Runs a single instruction (or one idle m-cycle while halted) and services interrupts.
+    // While stopped the whole system is frozen until a joypad line goes low, only total_cycles moves on
+    // so the host still sees time passing.
+    pub fn step(&mut self) {
+        if self.stopped {
+            self.total_cycles += 4;
+            if self.input_states.joyp(self.memory.read(0xFF00)) & 0x0F != 0x0F {
+                self.stopped = false;
+            }
+            return;
+        }
+        if !self.halted {
+            let opcode = self.fetch();
+            self.execute(opcode);
+        } else { self.m_cycle(); }
+        self.interrupt_poll();
+    }
+
+    // Trace line in the gameboy-doctor layout, with the decoded instruction at PC (and its label) appended
+    pub fn trace_line(&self, symbols: &SymbolTable) -> String {
+        let instruction = disassembler::disassemble(&self.memory, self.pc);
+        let target_bank = instruction.target.map(symbols::mapped_bank).unwrap_or(0);
+        let label = match symbols.label_at(self.pc) {
+            Some(label) => format!("{}: ", label),
+            None => String::new(),
+        };
+        format!(
+            "A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X}) {}{}",
+            self.registers.get_reg(Reg::A),
+            self.registers.get_reg(Reg::F),
+            self.registers.get_reg(Reg::B),
+            self.registers.get_reg(Reg::C),
+            self.registers.get_reg(Reg::D),
+            self.registers.get_reg(Reg::E),
+            self.registers.get_reg(Reg::H),
+            self.registers.get_reg(Reg::L),
+            self.sp,
+            self.pc,
+            self.memory.read(self.pc),
+            self.memory.read(self.pc.wrapping_add(1)),
+            self.memory.read(self.pc.wrapping_add(2)),
+            self.memory.read(self.pc.wrapping_add(3)),
+            label,
+            disassembler::labelled_mnemonic(&instruction, symbols, target_bank),
+        )
     }
 
     pub fn m_cycle(&mut self) {
@@ -380,6 +534,7 @@ impl CPU {
         }
 
         self.t_cycles = self.t_cycles.wrapping_add(4);
+        self.total_cycles += 4;
         if !self.ppu.enabled {
             let lcdc = self.memory.read(0xFF40);
             if lcdc & 0b1000_0000 == 0b1000_0000 {
@@ -464,6 +619,7 @@ impl CPU {
         self.set_vblank_flag();
         self.set_tima_flag();
         self.set_stat_flag();
+        self.input_states.update(self.memory.read(0xFF00));
         self.set_input_flag();
         let interrupt_enable = self.memory.read(0xFFFF);
         let interrupt_flags = self.memory.read(0xFF0F);
@@ -544,23 +700,23 @@ impl CPU {
     }
 
     pub fn write(&mut self, address: u16, data: u8, ) {
-        match address {
-            0xFF04..=0xFF07 => {
-                self.timer.write_io(address, data);
-            },
-            _ => { 
-                self.memory.write(address, data); 
-            },
-        }
+        self.bus_write(address, data);
         if address == 0xFF0F { println!("@ {:x}", self.pc); }
         self.m_cycle();
     }
 
     pub fn read(&mut self, address: u16) -> u8 {
-        let data = match address {
+        let data = self.bus_read(address);
+        self.m_cycle();
+        data
+    }
+
+    // What the game sees at an address, without taking a cycle, so the debugger can look at the same values
+    pub fn bus_read(&mut self, address: u16) -> u8 {
+        match address {
             0xFF00 => {
-                let joyp = self.memory.read(0xFF00);
-                self.input_states.get_states(joyp)
+                let select = self.memory.read(0xFF00);
+                self.input_states.joyp(select)
             },
             0xFF04..=0xFF07 => {
                 self.timer.read_io(address)
@@ -568,9 +724,18 @@ impl CPU {
             _ => { 
                 self.memory.read(address)
             },
-        };
-        self.m_cycle();
-        data
+        }
+    }
+
+    pub fn bus_write(&mut self, address: u16, data: u8) {
+        match address {
+            0xFF04..=0xFF07 => {
+                self.timer.write_io(address, data);
+            },
+            _ => { 
+                self.memory.write(address, data); 
+            },
+        }
     }
 
     pub fn stack_push(&mut self, num:u16){
@@ -611,7 +776,7 @@ impl CPU {
                 0xd => { self.dec_reg(Reg::C); },
                 0xe => { self.reg_ld_operand(Reg::C); },
                 0xf => { self.rrca(); },
-                0x10 => {  },
+                0x10 => { self.stop(); },
                 0x11 => { self.regW_ld_operand(RegW::DE); },
                 0x12 => { self.regWaddr_ld_reg(RegW::DE, Reg::A); },
                 0x13 => { self.inc_regW(RegW::DE); },
@@ -929,6 +1094,13 @@ impl CPU {
             }
         }
     }
+    // STOP is 2 bytes, the second is ignored. DIV is reset as the clock stops
+    pub fn stop(&mut self) {
+        self.fetch();
+        self.timer.write_io(0xFF04, 0);
+        self.stopped = true;
+    }
+
     // LD
     // Load a register with another register
     pub fn reg_ld_reg(&mut self, dst: Reg, src: Reg) {
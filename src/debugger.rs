@@ -0,0 +1,178 @@
+use crate::cpu::CPU;
+use crate::disassembler;
+use crate::registers::*;
+use crate::symbols::SymbolTable;
+
+use std::io::{self, Write};
+
+// Terminal debugger. The emulator loop asks should_break() before every instruction,
+// and hands control to prompt() when it returns true.
+//
+// Commands:
+//   s / step [n]        step n instructions (default 1)
+//   c / continue        resume until the next breakpoint
+//   l / list [addr]     disassemble around addr (default PC)
+//   b / break <addr>    set a breakpoint
+//   d / delete <addr>   remove a breakpoint
+//   bl                  list breakpoints
+//   r / regs            show registers
+//   x <addr> [len]      dump memory
+// Addresses can be given as hex ($0150, 0x150, 150), bank:address (00:0150) or a symbol name (Main.loop)
+pub struct Debugger {
+    pub breakpoints: Vec<u16>,
+    pub symbols: SymbolTable,
+    pub paused: bool,
+    steps_remaining: u32,
+    resumed_from: Option<u16>, // breakpoint we just continued from, so it doesn't fire again immediately
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            symbols: SymbolTable::new(),
+            paused: false,
+            steps_remaining: 0,
+            resumed_from: None,
+        }
+    }
+
+    pub fn should_break(&mut self, cpu: &CPU) -> bool {
+        if self.steps_remaining > 0 {
+            self.steps_remaining -= 1;
+            if self.steps_remaining == 0 { self.paused = true; }
+            return false;
+        }
+        if self.paused {
+            return true;
+        }
+        if let Some(address) = self.resumed_from {
+            if address == cpu.pc { return false; }
+            self.resumed_from = None;
+        }
+        if self.breakpoints.contains(&cpu.pc) {
+            println!("BREAKPOINT HIT @ {}", self.symbols.describe(cpu.pc));
+            self.paused = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn prompt(&mut self, cpu: &mut CPU) {
+        self.print_listing(cpu, cpu.pc);
+        loop {
+            print!("(nemu) ");
+            io::stdout().flush().unwrap();
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                self.resume(cpu);
+                return;
+            }
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.as_slice() {
+                ["s"] | ["step"] => { self.step(1); return; },
+                ["s", n] | ["step", n] => {
+                    match n.parse::<u32>() {
+                        Ok(n) if n > 0 => { self.step(n); return; },
+                        _ => println!("INVALID STEP COUNT: {}", n),
+                    }
+                },
+                ["c"] | ["continue"] => { self.resume(cpu); return; },
+                ["l"] | ["list"] => self.print_listing(cpu, cpu.pc),
+                ["l", addr] | ["list", addr] => {
+                    match self.parse_address(addr) {
+                        Some(address) => self.print_listing(cpu, address),
+                        None => println!("INVALID ADDRESS: {}", addr),
+                    }
+                },
+                ["b", addr] | ["break", addr] => {
+                    match self.parse_address(addr) {
+                        Some(address) => {
+                            if !self.breakpoints.contains(&address) { self.breakpoints.push(address); }
+                            println!("BREAKPOINT SET @ {}", self.symbols.describe(address));
+                        },
+                        None => println!("INVALID ADDRESS: {}", addr),
+                    }
+                },
+                ["d", addr] | ["delete", addr] => {
+                    match self.parse_address(addr) {
+                        Some(address) => {
+                            self.breakpoints.retain(|b| *b != address);
+                            println!("BREAKPOINT REMOVED @ {}", self.symbols.describe(address));
+                        },
+                        None => println!("INVALID ADDRESS: {}", addr),
+                    }
+                },
+                ["bl"] => {
+                    for address in &self.breakpoints {
+                        println!("{}", self.symbols.describe(*address));
+                    }
+                },
+                ["r"] | ["regs"] => self.print_registers(cpu),
+                ["x", addr] => self.dump_memory(cpu, addr, "16"),
+                ["x", addr, len] => self.dump_memory(cpu, addr, len),
+                [] => {},
+                _ => println!("UNKNOWN COMMAND: {}", line.trim()),
+            }
+        }
+    }
+
+    fn step(&mut self, n: u32) {
+        self.steps_remaining = n;
+        self.paused = false;
+    }
+
+    fn resume(&mut self, cpu: &CPU) {
+        self.paused = false;
+        self.resumed_from = Some(cpu.pc);
+    }
+
+    pub fn parse_address(&self, text: &str) -> Option<u16> {
+        self.symbols.resolve(text)
+    }
+
+    pub fn print_listing(&self, cpu: &CPU, address: u16) {
+        for instruction in disassembler::listing(&cpu.memory, address, 5, 8) {
+            if let Some(label) = self.symbols.label_at(instruction.address) {
+                println!("{}:", label);
+            }
+            let marker = if instruction.address == cpu.pc { "=>" }
+                else if self.breakpoints.contains(&instruction.address) { " *" }
+                else { "  " };
+            println!("{} {}", marker, disassembler::format_instruction(&instruction, &self.symbols));
+        }
+    }
+
+    fn print_registers(&self, cpu: &CPU) {
+        println!("AF: {:04X} BC: {:04X} DE: {:04X} HL: {:04X} SP: {:04X} PC: {:04X}",
+            cpu.registers.get_regW(RegW::AF),
+            cpu.registers.get_regW(RegW::BC),
+            cpu.registers.get_regW(RegW::DE),
+            cpu.registers.get_regW(RegW::HL),
+            cpu.sp,
+            cpu.pc,
+        );
+        println!("Z: {} N: {} H: {} C: {} IME: {} HALTED: {}",
+            cpu.registers.get_flag(Flag::Z) as u8,
+            cpu.registers.get_flag(Flag::N) as u8,
+            cpu.registers.get_flag(Flag::H) as u8,
+            cpu.registers.get_flag(Flag::C) as u8,
+            cpu.ime as u8,
+            cpu.halted as u8,
+        );
+    }
+
+    fn dump_memory(&self, cpu: &CPU, addr: &str, len: &str) {
+        let (address, len) = match (self.parse_address(addr), len.parse::<u16>()) {
+            (Some(address), Ok(len)) => (address, len),
+            _ => { println!("USAGE: x <addr> [len]"); return; }
+        };
+        for row in (0..len).step_by(16) {
+            let start = address.wrapping_add(row);
+            let bytes: Vec<String> = (0..16.min(len - row))
+                .map(|i| format!("{:02X}", cpu.memory.read(start.wrapping_add(i))))
+                .collect();
+            println!("{:04X}: {}", start, bytes.join(" "));
+        }
+    }
+}
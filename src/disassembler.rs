@@ -0,0 +1,283 @@
+use crate::memory::Memory;
+use crate::symbols::{self, SymbolTable};
+
+// Decodes SM83 opcodes into mnemonics. Mirrors the dispatch in CPU::execute, so anything
+// that is a no-op there (the unused opcodes) is shown as ILLEGAL rather than guessed at.
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB A,", "SBC A,", "AND A,", "XOR A,", "OR A,", "CP A,"];
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub target: Option<u16>, // absolute destination of jumps, calls and rsts, used for labels
+}
+
+impl Instruction {
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+}
+
+// Reads up to 3 bytes starting at address straight from memory, without ticking the cpu
+pub fn disassemble(memory: &Memory, address: u16) -> Instruction {
+    let bytes = [
+        memory.read(address),
+        memory.read(address.wrapping_add(1)),
+        memory.read(address.wrapping_add(2)),
+    ];
+    decode(address, &bytes)
+}
+
+// Decodes a single instruction. Missing operand bytes (end of a buffer) are read as 0
+pub fn decode(address: u16, bytes: &[u8]) -> Instruction {
+    let byte = |i: usize| -> u8 { *bytes.get(i).unwrap_or(&0) };
+    let opcode = byte(0);
+    let d8 = byte(1);
+    let d16 = (byte(2) as u16) << 8 | byte(1) as u16;
+    let relative = address.wrapping_add(2).wrapping_add_signed(d8 as i8 as i16);
+
+    let mut target = None;
+    let (mnemonic, length) = match opcode {
+        0x00 => ("NOP".to_string(), 1),
+        0x10 => ("STOP".to_string(), 2),
+        0x76 => ("HALT".to_string(), 1),
+        0xCB => (decode_cb(d8), 2),
+
+        // 16 bit loads and arithmetic
+        0x01 | 0x11 | 0x21 | 0x31 => (format!("LD {}, ${:04X}", R16[(opcode >> 4) as usize], d16), 3),
+        0x03 | 0x13 | 0x23 | 0x33 => (format!("INC {}", R16[(opcode >> 4) as usize]), 1),
+        0x0B | 0x1B | 0x2B | 0x3B => (format!("DEC {}", R16[(opcode >> 4) as usize]), 1),
+        0x09 | 0x19 | 0x29 | 0x39 => (format!("ADD HL, {}", R16[(opcode >> 4) as usize]), 1),
+        0x08 => (format!("LD [${:04X}], SP", d16), 3),
+
+        // Indirect loads
+        0x02 => ("LD [BC], A".to_string(), 1),
+        0x12 => ("LD [DE], A".to_string(), 1),
+        0x22 => ("LD [HL+], A".to_string(), 1),
+        0x32 => ("LD [HL-], A".to_string(), 1),
+        0x0A => ("LD A, [BC]".to_string(), 1),
+        0x1A => ("LD A, [DE]".to_string(), 1),
+        0x2A => ("LD A, [HL+]".to_string(), 1),
+        0x3A => ("LD A, [HL-]".to_string(), 1),
+
+        // 8 bit inc / dec / immediate loads
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => (format!("INC {}", R8[(opcode >> 3) as usize]), 1),
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => (format!("DEC {}", R8[(opcode >> 3) as usize]), 1),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => (format!("LD {}, ${:02X}", R8[(opcode >> 3) as usize], d8), 2),
+
+        // Accumulator ops
+        0x07 => ("RLCA".to_string(), 1),
+        0x0F => ("RRCA".to_string(), 1),
+        0x17 => ("RLA".to_string(), 1),
+        0x1F => ("RRA".to_string(), 1),
+        0x27 => ("DAA".to_string(), 1),
+        0x2F => ("CPL".to_string(), 1),
+        0x37 => ("SCF".to_string(), 1),
+        0x3F => ("CCF".to_string(), 1),
+
+        // Relative jumps
+        0x18 => { target = Some(relative); (format!("JR ${:04X}", relative), 2) },
+        0x20 | 0x28 | 0x30 | 0x38 => {
+            target = Some(relative);
+            (format!("JR {}, ${:04X}", CONDITIONS[((opcode >> 3) & 3) as usize], relative), 2)
+        },
+
+        // Register to register loads and the ALU block
+        0x40..=0x7F => (format!("LD {}, {}", R8[((opcode >> 3) & 7) as usize], R8[(opcode & 7) as usize]), 1),
+        0x80..=0xBF => (format!("{} {}", ALU[((opcode >> 3) & 7) as usize], R8[(opcode & 7) as usize]), 1),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => (format!("{} ${:02X}", ALU[((opcode >> 3) & 7) as usize], d8), 2),
+
+        // Control flow
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => (format!("RET {}", CONDITIONS[((opcode >> 3) & 3) as usize]), 1),
+        0xC9 => ("RET".to_string(), 1),
+        0xD9 => ("RETI".to_string(), 1),
+        0xC2 | 0xCA | 0xD2 | 0xDA => {
+            target = Some(d16);
+            (format!("JP {}, ${:04X}", CONDITIONS[((opcode >> 3) & 3) as usize], d16), 3)
+        },
+        0xC3 => { target = Some(d16); (format!("JP ${:04X}", d16), 3) },
+        0xE9 => ("JP HL".to_string(), 1),
+        0xC4 | 0xCC | 0xD4 | 0xDC => {
+            target = Some(d16);
+            (format!("CALL {}, ${:04X}", CONDITIONS[((opcode >> 3) & 3) as usize], d16), 3)
+        },
+        0xCD => { target = Some(d16); (format!("CALL ${:04X}", d16), 3) },
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+            target = Some((opcode & 0x38) as u16);
+            (format!("RST ${:02X}", opcode & 0x38), 1)
+        },
+
+        // Stack
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => (format!("POP {}", R16_STACK[((opcode >> 4) & 3) as usize]), 1),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => (format!("PUSH {}", R16_STACK[((opcode >> 4) & 3) as usize]), 1),
+        0xE8 => (format!("ADD SP, {}", d8 as i8), 2),
+        0xF8 => (format!("LD HL, SP{:+}", d8 as i8), 2),
+        0xF9 => ("LD SP, HL".to_string(), 1),
+
+        // High ram and absolute loads
+        0xE0 => (format!("LDH [$FF{:02X}], A", d8), 2),
+        0xF0 => (format!("LDH A, [$FF{:02X}]", d8), 2),
+        0xE2 => ("LD [$FF00+C], A".to_string(), 1),
+        0xF2 => ("LD A, [$FF00+C]".to_string(), 1),
+        0xEA => (format!("LD [${:04X}], A", d16), 3),
+        0xFA => (format!("LD A, [${:04X}]", d16), 3),
+
+        // Interrupts
+        0xF3 => ("DI".to_string(), 1),
+        0xFB => ("EI".to_string(), 1),
+
+        // D3, DB, DD, E3, E4, EB, EC, ED, F4, FC, FD
+        _ => (format!("ILLEGAL ${:02X}", opcode), 1),
+    };
+
+    Instruction {
+        address,
+        bytes: (0..length).map(|i| byte(i)).collect(),
+        mnemonic,
+        target,
+    }
+}
+
+fn decode_cb(opcode: u8) -> String {
+    let register = R8[(opcode & 7) as usize];
+    let bit = (opcode >> 3) & 7;
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATES[bit as usize], register),
+        1 => format!("BIT {}, {}", bit, register),
+        2 => format!("RES {}, {}", bit, register),
+        3 => format!("SET {}, {}", bit, register),
+        _ => unreachable!(),
+    }
+}
+
+// Formats an instruction as "ADDR: BYTES  MNEMONIC", naming jump targets from the symbol table
+pub fn format_instruction(instruction: &Instruction, symbols: &SymbolTable) -> String {
+    let target_bank = instruction.target.map(symbols::mapped_bank).unwrap_or(0);
+    format_with_bank(instruction, symbols, target_bank)
+}
+
+fn format_with_bank(instruction: &Instruction, symbols: &SymbolTable, target_bank: u16) -> String {
+    let bytes: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:04X}: {:<9} {}", instruction.address, bytes.join(" "), labelled_mnemonic(instruction, symbols, target_bank))
+}
+
+pub fn labelled_mnemonic(instruction: &Instruction, symbols: &SymbolTable, target_bank: u16) -> String {
+    let target = match instruction.target {
+        Some(target) => target,
+        None => return instruction.mnemonic.clone(),
+    };
+    match symbols.label(target_bank, target) {
+        Some(label) => instruction.mnemonic.replace(&format!("${:04X}", target), label),
+        None => instruction.mnemonic.clone(),
+    }
+}
+
+// Disassembles a window of instructions around pc. Instructions are variable length, so
+// decoding backwards is ambiguous - instead we look for the furthest start address before
+// pc whose instruction stream lands exactly on pc.
+pub fn listing(memory: &Memory, pc: u16, before: usize, after: usize) -> Vec<Instruction> {
+    let mut start = pc;
+    for distance in (1..=(before as u16 * 3)).rev() {
+        let candidate = pc.wrapping_sub(distance);
+        let mut address = candidate;
+        let mut count = 0;
+        while address != pc && count <= before {
+            address = address.wrapping_add(disassemble(memory, address).length());
+            count += 1;
+            if address.wrapping_sub(candidate) > distance { break; }
+        }
+        if address == pc && count <= before {
+            start = candidate;
+            break;
+        }
+    }
+
+    let mut instructions = Vec::new();
+    let mut address = start;
+    while address != pc {
+        let instruction = disassemble(memory, address);
+        address = address.wrapping_add(instruction.length());
+        instructions.push(instruction);
+    }
+    for _ in 0..=after {
+        let instruction = disassemble(memory, address);
+        address = address.wrapping_add(instruction.length());
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/////////////////////////////// ROM BANKS ////////////////////////////////
+
+const BANK_SIZE: usize = 0x4000;
+
+pub fn vector_label(address: u16) -> Option<&'static str> {
+    match address {
+        0x00 => Some("RST_00"),
+        0x08 => Some("RST_08"),
+        0x10 => Some("RST_10"),
+        0x18 => Some("RST_18"),
+        0x20 => Some("RST_20"),
+        0x28 => Some("RST_28"),
+        0x30 => Some("RST_30"),
+        0x38 => Some("RST_38"),
+        0x40 => Some("VBlankInterrupt"),
+        0x48 => Some("STATInterrupt"),
+        0x50 => Some("TimerInterrupt"),
+        0x58 => Some("SerialInterrupt"),
+        0x60 => Some("JoypadInterrupt"),
+        0x100 => Some("Entry"),
+        0x104 => Some("Header"),
+        _ => None,
+    }
+}
+
+// Disassembles a whole 16KiB bank of a rom image. Bank 0 is mapped at 0000, every other bank at 4000.
+// The cartridge header (0104 -> 014F) is dumped as data rather than decoded.
+// Labels come from the symbol table where available, falling back to the rst / interrupt vectors.
+pub fn disassemble_bank(rom: &[u8], bank: usize, symbols: &SymbolTable) -> String {
+    let start = bank * BANK_SIZE;
+    let end = (start + BANK_SIZE).min(rom.len());
+    let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let section = if bank == 0 { "ROM0[$0000]".to_string() } else { format!("ROMX[$4000], BANK[{}]", bank) };
+    let mut output = format!("SECTION \"ROM Bank ${:03X}\", {}\n", bank, section);
+    if start >= rom.len() {
+        return output;
+    }
+
+    let mut offset = start;
+    while offset < end {
+        let address = base + (offset - start) as u16;
+        let label = match symbols.label(bank as u16, address) {
+            Some(label) => Some(label),
+            None if bank == 0 => vector_label(address),
+            None => None,
+        };
+        if let Some(label) = label {
+            output += &format!("\n{}:\n", label);
+        }
+
+        if bank == 0 && (0x104..0x150).contains(&address) {
+            let row_end = (offset + 16).min(start + 0x150).min(end);
+            let bytes: Vec<String> = rom[offset..row_end].iter().map(|b| format!("${:02X}", b)).collect();
+            output += &format!("    {:04X}: DB {}\n", address, bytes.join(", "));
+            offset = row_end;
+            continue;
+        }
+
+        let instruction = decode(address, &rom[offset..end]);
+        let target_bank = match instruction.target {
+            Some(0x0000..=0x3FFF) => 0,
+            _ => bank as u16,
+        };
+        output += &format!("    {}\n", format_with_bank(&instruction, symbols, target_bank));
+        offset += instruction.bytes.len();
+    }
+    output
+}
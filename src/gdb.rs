@@ -0,0 +1,283 @@
+use crate::cpu::CPU;
+use crate::registers::*;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use sdl2::event::Event;
+
+// GDB Remote Serial Protocol stub.
+// There is no upstream SM83 target in GDB, so registers follow the layout of GDB's z80 target,
+// which frontends that speak "gameboy" generally reuse. Every register is 16 bits, little endian:
+//   0 AF | 1 BC | 2 DE | 3 HL | 4 SP | 5 PC
+// Supported packets: ? g G p P m M Z0 z0 s c k D qSupported qAttached H, and Ctrl-C while running.
+
+const REGISTER_COUNT: usize = 6;
+const PACKET_SIZE: usize = 0x4000; // the biggest packet gdb is told it can send, advertised in qSupported
+const STEPS_BETWEEN_POLLS: u32 = 10000; // how often a running target checks the socket / sdl window
+
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: Vec<u16>,
+    no_ack: bool,
+}
+
+// Blocks until a debugger connects on 127.0.0.1:port, then serves it until it detaches or kills the target
+pub fn serve(cpu: &mut CPU, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("WAITING FOR GDB ON 127.0.0.1:{}", port);
+    let (stream, address) = listener.accept()?;
+    println!("GDB CONNECTED FROM {}", address);
+    stream.set_nodelay(true)?;
+
+    let mut stub = GdbStub {
+        stream,
+        breakpoints: Vec::new(),
+        no_ack: false,
+    };
+    stub.run(cpu)
+}
+
+impl GdbStub {
+    fn run(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(()), // connection closed
+            };
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'?') => "S05".to_string(),
+                Some(b'g') => self.read_registers(cpu),
+                Some(b'G') => self.write_registers(cpu, &packet[1..]),
+                Some(b'p') => self.read_register(cpu, &packet[1..]),
+                Some(b'P') => self.write_register(cpu, &packet[1..]),
+                Some(b'm') => self.read_memory(cpu, &packet[1..]),
+                Some(b'M') => self.write_memory(cpu, &packet[1..]),
+                Some(b'Z') => self.set_breakpoint(&packet[1..], true),
+                Some(b'z') => self.set_breakpoint(&packet[1..], false),
+                Some(b's') => { cpu.step(); "S05".to_string() },
+                Some(b'c') => self.resume(cpu)?,
+                Some(b'H') => "OK".to_string(),
+                Some(b'k') => return Ok(()),
+                Some(b'D') => { self.send_packet("OK")?; return Ok(()); },
+                _ if packet.starts_with("qSupported") => format!("PacketSize={:x};swbreak+;QStartNoAckMode+", PACKET_SIZE),
+                _ if packet.starts_with("QStartNoAckMode") => { self.send_packet("OK")?; self.no_ack = true; continue; },
+                _ if packet.starts_with("qAttached") => "1".to_string(),
+                _ => String::new(), // empty reply = unsupported
+            };
+            self.send_packet(&reply)?;
+        }
+    }
+
+    // Runs until a breakpoint is hit, gdb sends an interrupt (0x03) or the window is closed
+    fn resume(&mut self, cpu: &mut CPU) -> io::Result<String> {
+        let mut steps = 0;
+        loop {
+            cpu.step();
+            if self.breakpoints.contains(&cpu.pc) {
+                return Ok("T05swbreak:;".to_string());
+            }
+
+            steps += 1;
+            if steps == STEPS_BETWEEN_POLLS {
+                steps = 0;
+                if self.interrupt_requested()? {
+                    return Ok("S02".to_string());
+                }
+                if let Some(renderer) = cpu.ppu.renderer.as_mut() {
+                    for event in renderer.event_pump.poll_iter() {
+                        if let Event::Quit {..} = event {
+                            return Ok("X00".to_string());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0_u8; 1];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /////////////////////////////// PACKETS ////////////////////////////////
+
+    // Reads one $packet#xx, returning its payload. Interrupts outside of a packet read as "\x03"
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0_u8; 1];
+        loop {
+            if self.stream.read(&mut byte)? == 0 { return Ok(None); }
+            match byte[0] {
+                b'$' => break,
+                0x03 => return Ok(Some("?".to_string())), // stray interrupt while stopped, report stop reason
+                _ => {}, // acks and noise
+            }
+        }
+
+        let mut payload = Vec::new();
+        loop {
+            if self.stream.read(&mut byte)? == 0 { return Ok(None); }
+            if byte[0] == b'#' { break; }
+            payload.push(byte[0]);
+        }
+        let mut checksum = [0_u8; 2];
+        self.stream.read_exact(&mut checksum)?;
+
+        let expected = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap_or("00"), 16).unwrap_or(0);
+        let actual = payload.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b));
+        if !self.no_ack {
+            self.stream.write_all(if expected == actual { b"+" } else { b"-" })?;
+        }
+        if expected != actual {
+            return self.read_packet(); // gdb resends on a nack
+        }
+        Ok(Some(String::from_utf8_lossy(&payload).to_string()))
+    }
+
+    fn send_packet(&mut self, payload: &str) -> io::Result<()> {
+        let checksum = payload.bytes().fold(0_u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", payload, checksum)?;
+        self.stream.flush()
+    }
+
+    /////////////////////////////// REGISTERS ////////////////////////////////
+
+    fn get_register(cpu: &CPU, n: usize) -> u16 {
+        match n {
+            0 => cpu.registers.get_regW(RegW::AF),
+            1 => cpu.registers.get_regW(RegW::BC),
+            2 => cpu.registers.get_regW(RegW::DE),
+            3 => cpu.registers.get_regW(RegW::HL),
+            4 => cpu.sp,
+            5 => cpu.pc,
+            _ => 0,
+        }
+    }
+
+    fn set_register(cpu: &mut CPU, n: usize, value: u16) {
+        match n {
+            0 => cpu.registers.set_regW(RegW::AF, value),
+            1 => cpu.registers.set_regW(RegW::BC, value),
+            2 => cpu.registers.set_regW(RegW::DE, value),
+            3 => cpu.registers.set_regW(RegW::HL, value),
+            4 => cpu.sp = value,
+            5 => cpu.pc = value,
+            _ => {},
+        }
+    }
+
+    fn read_registers(&self, cpu: &CPU) -> String {
+        (0..REGISTER_COUNT).map(|n| encode_u16(Self::get_register(cpu, n))).collect()
+    }
+
+    fn write_registers(&self, cpu: &mut CPU, data: &str) -> String {
+        for n in 0..REGISTER_COUNT {
+            match data.get(n * 4..n * 4 + 4).and_then(decode_u16) {
+                Some(value) => Self::set_register(cpu, n, value),
+                None => return "E01".to_string(),
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, cpu: &CPU, data: &str) -> String {
+        match usize::from_str_radix(data, 16) {
+            Ok(n) if n < REGISTER_COUNT => encode_u16(Self::get_register(cpu, n)),
+            Ok(_) => "xxxx".to_string(),
+            Err(_) => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&self, cpu: &mut CPU, data: &str) -> String {
+        let (n, value) = match data.split_once('=') {
+            Some(parts) => parts,
+            None => return "E01".to_string(),
+        };
+        match (usize::from_str_radix(n, 16), decode_u16(value)) {
+            (Ok(n), Some(value)) if n < REGISTER_COUNT => { Self::set_register(cpu, n, value); "OK".to_string() },
+            _ => "E01".to_string(),
+        }
+    }
+
+    /////////////////////////////// MEMORY ////////////////////////////////
+
+    // Through the cpu's bus, so JOYP and the timer read as the game sees them
+    fn read_memory(&self, cpu: &mut CPU, data: &str) -> String {
+        let (address, length) = match parse_range(data) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        (0..length).map(|i| format!("{:02x}", cpu.bus_read(address.wrapping_add(i)))).collect()
+    }
+
+    fn write_memory(&self, cpu: &mut CPU, data: &str) -> String {
+        let (range, bytes) = match data.split_once(':') {
+            Some(parts) => parts,
+            None => return "E01".to_string(),
+        };
+        let (address, length) = match parse_range(range) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        for i in 0..length {
+            let byte = match bytes.get(i as usize * 2..i as usize * 2 + 2).map(|b| u8::from_str_radix(b, 16)) {
+                Some(Ok(byte)) => byte,
+                _ => return "E01".to_string(),
+            };
+            let address = address.wrapping_add(i);
+            match address { // rom isn't writable through the bus, so patch the banks directly
+                0x0000..=0x3FFF => cpu.memory.rom_bank_0[address as usize] = byte,
+                0x4000..=0x7FFF => cpu.memory.rom_bank_n[address as usize - 0x4000] = byte,
+                _ => cpu.bus_write(address, byte),
+            }
+        }
+        "OK".to_string()
+    }
+
+    /////////////////////////////// BREAKPOINTS ////////////////////////////////
+
+    fn set_breakpoint(&mut self, data: &str, insert: bool) -> String {
+        let mut fields = data.split(',');
+        let kind = fields.next();
+        let address = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+        match (kind, address) {
+            (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                if insert {
+                    if !self.breakpoints.contains(&address) { self.breakpoints.push(address); }
+                } else {
+                    self.breakpoints.retain(|b| *b != address);
+                }
+                "OK".to_string()
+            },
+            (Some(_), Some(_)) => String::new(), // watchpoints are unsupported
+            _ => "E01".to_string(),
+        }
+    }
+}
+
+fn encode_u16(value: u16) -> String {
+    format!("{:02x}{:02x}", value as u8, (value >> 8) as u8)
+}
+
+fn decode_u16(data: &str) -> Option<u16> {
+    let low = u8::from_str_radix(data.get(0..2)?, 16).ok()?;
+    let high = u8::from_str_radix(data.get(2..4)?, 16).ok()?;
+    Some((high as u16) << 8 | low as u16)
+}
+
+// Lengths are capped so the hex for them fits in a packet
+fn parse_range(data: &str) -> Option<(u16, u16)> {
+    let (address, length) = data.split_once(',')?;
+    let address = u32::from_str_radix(address, 16).ok()?;
+    let length = u16::from_str_radix(length, 16).ok().filter(|&length| length as usize * 2 <= PACKET_SIZE)?;
+    Some((address as u16, length))
+}
@@ -0,0 +1,65 @@
+use crate::storage::Session;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Play statistics built from the sessions in storage, for the TUI's stats tab
+
+pub struct GameStats {
+    pub guid: i32,
+    pub title: String,
+    pub sessions: usize,
+    pub play_time: i64, // seconds
+    pub best: i32,
+}
+
+// Totals per game, most played first
+pub fn per_game(sessions: &[Session]) -> Vec<GameStats> {
+    let mut games: Vec<GameStats> = Vec::new();
+    for session in sessions {
+        let index = match games.iter().position(|game| game.guid == session.guid) {
+            Some(index) => index,
+            None => {
+                games.push(GameStats { guid: session.guid, title: session.title.clone(), sessions: 0, play_time: 0, best: 0 });
+                games.len() - 1
+            },
+        };
+        let game = &mut games[index];
+        game.sessions += 1;
+        game.play_time += session.duration as i64;
+        game.best = game.best.max(session.peak_score);
+    }
+    games.sort_by(|a, b| b.play_time.cmp(&a.play_time));
+    games
+}
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs() as i64).unwrap_or(0)
+}
+
+// 3h 12m, 5m 03s or 42s
+pub fn format_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// YYYY-MM-DD HH:MM in UTC
+pub fn format_date(timestamp: i64) -> String {
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+    // Days to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, seconds / 3600, seconds / 60 % 60)
+}
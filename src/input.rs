@@ -0,0 +1,429 @@
+use crate::cpu::InputStates;
+use crate::pacer::{CYCLES_PER_FRAME, CYCLES_PER_SECOND};
+
+use sdl2::{
+    controller::{Axis, Button as PadButton, GameController},
+    event::Event,
+    keyboard::Keycode,
+    GameControllerSubsystem,
+    Sdl,
+};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Joypad bindings. Each Game Boy button, turbo button and macro slot can be bound to any number of
+// keyboard keys, gamepad buttons and gamepad axis directions. Bindings are kept in a plain text file, one per line:
+//
+//   # action = binding
+//   a = key:Q
+//   a = pad:a
+//   up = axis:lefty-
+//
+// Key names are SDL's (Keycode::name), pad buttons and axes use SDL's GameController names.
+
+const AXIS_THRESHOLD: i16 = 16_000; // roughly half way, so a resting stick doesn't hold a direction
+
+// The emulator's own keys while a game runs, which can't be bound since a bound key never reaches them
+pub const HOTKEYS: [Keycode; 16] = [
+    Keycode::Escape, Keycode::T, Keycode::Y, Keycode::U, Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+    Keycode::I, Keycode::P, Keycode::Tab, Keycode::Backspace, Keycode::F5, Keycode::F6, Keycode::F7, Keycode::Backquote,
+];
+
+/////////////////////////////// ACTIONS ////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Button {
+    Down,
+    Up,
+    Left,
+    Right,
+    Start,
+    Select,
+    B,
+    A,
+}
+
+// Ordered as they are in JOYP
+pub const BUTTONS: [Button; 8] = [
+    Button::Down,
+    Button::Up,
+    Button::Left,
+    Button::Right,
+    Button::Start,
+    Button::Select,
+    Button::B,
+    Button::A,
+];
+
+pub const MACRO_SLOTS: usize = 4;
+
+impl Button {
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Down => "down",
+            Button::Up => "up",
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::Start => "start",
+            Button::Select => "select",
+            Button::B => "b",
+            Button::A => "a",
+        }
+    }
+
+    // The button's bit in InputStates::pack
+    pub fn mask(self) -> u8 {
+        0b1000_0000 >> BUTTONS.iter().position(|button| *button == self).unwrap()
+    }
+}
+
+// Anything an input can be bound to: a joypad button, an autofire version of one, or a macro slot
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Button(Button),
+    Turbo(Button),
+    Macro(usize),
+}
+
+impl Action {
+    // Everything that can be bound, in the order the TUI lists them
+    pub fn all() -> Vec<Action> {
+        let mut actions: Vec<Action> = BUTTONS.iter().map(|button| Action::Button(*button)).collect();
+        actions.push(Action::Turbo(Button::A));
+        actions.push(Action::Turbo(Button::B));
+        actions.extend((1..=MACRO_SLOTS).map(Action::Macro));
+        actions
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Action::Button(button) => button.name().to_string(),
+            Action::Turbo(button) => format!("turbo_{}", button.name()),
+            Action::Macro(slot) => format!("macro{}", slot),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Action::all().into_iter().find(|action| action.name() == name)
+    }
+}
+
+/////////////////////////////// BINDINGS ////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Binding {
+    Key(Keycode),
+    Pad(PadButton),
+    Axis(Axis, bool), // true => positive direction
+}
+
+impl Binding {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (kind, name) = text.trim().split_once(':')?;
+        match kind.trim() {
+            "key" => Keycode::from_name(name.trim()).map(Binding::Key),
+            "pad" => PadButton::from_string(name.trim()).map(Binding::Pad),
+            "axis" => {
+                let name = name.trim();
+                let positive = match name.chars().last()? {
+                    '+' => true,
+                    '-' => false,
+                    _ => return None,
+                };
+                Axis::from_string(&name[..name.len() - 1]).map(|axis| Binding::Axis(axis, positive))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn is_key(&self) -> bool {
+        matches!(self, Binding::Key(_))
+    }
+
+    pub fn is_hotkey(&self) -> bool {
+        matches!(self, Binding::Key(keycode) if HOTKEYS.contains(keycode))
+    }
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Binding::Key(keycode) => write!(f, "key:{}", keycode.name()),
+            Binding::Pad(button) => write!(f, "pad:{}", button.string()),
+            Binding::Axis(axis, positive) => write!(f, "axis:{}{}", axis.string(), if *positive { '+' } else { '-' }),
+        }
+    }
+}
+
+pub struct Bindings {
+    pub path: PathBuf,
+    pub bindings: Vec<(Action, Binding)>,
+}
+
+impl Bindings {
+    // WASD => DPad, Q => A, E => B, R => Start, F => Select, plus the usual gamepad layout
+    pub fn default(path: &Path) -> Self {
+        let defaults = [
+            ("down", "key:S"), ("down", "pad:dpdown"), ("down", "axis:lefty+"),
+            ("up", "key:W"), ("up", "pad:dpup"), ("up", "axis:lefty-"),
+            ("left", "key:A"), ("left", "pad:dpleft"), ("left", "axis:leftx-"),
+            ("right", "key:D"), ("right", "pad:dpright"), ("right", "axis:leftx+"),
+            ("start", "key:R"), ("start", "pad:start"),
+            ("select", "key:F"), ("select", "pad:back"),
+            ("b", "key:E"), ("b", "pad:a"),
+            ("a", "key:Q"), ("a", "pad:b"),
+            ("turbo_a", "key:Z"), ("turbo_a", "pad:y"),
+            ("turbo_b", "key:X"), ("turbo_b", "pad:x"),
+            ("macro1", "key:F1"), ("macro2", "key:F2"), ("macro3", "key:F3"), ("macro4", "key:F4"),
+        ];
+        Bindings {
+            path: path.to_path_buf(),
+            bindings: defaults.iter().map(|(action, text)| (Action::from_name(action).unwrap(), Binding::parse(text).unwrap())).collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut bindings = Vec::new();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.split_once('=').and_then(|(action, binding)| {
+                Some((Action::from_name(action.trim())?, Binding::parse(binding).filter(|binding| !binding.is_hotkey())?))
+            });
+            match parsed {
+                Some(binding) => bindings.push(binding),
+                None => println!("IGNORING INVALID BINDING ON LINE {} OF {}: {}", number + 1, path.display(), line),
+            }
+        }
+        Ok(Bindings { path: path.to_path_buf(), bindings })
+    }
+
+    // Falls back to the defaults if the file doesn't exist yet
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            return Bindings::default(path);
+        }
+        match Bindings::load(path) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                println!("UNABLE TO READ {}: {}", path.display(), e);
+                Bindings::default(path)
+            },
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut text = String::from("# action = key:<SDL key name> | pad:<button> | axis:<axis>+/-\n");
+        for (action, binding) in self.bindings.iter() {
+            text += &format!("{} = {}\n", action.name(), binding);
+        }
+        fs::write(&self.path, text)
+    }
+
+    // Replaces the action's keyboard binding, or its gamepad bindings, with the new one.
+    // Whatever else was bound to the new input is unbound so one input never drives two actions.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.bindings.retain(|(bound_action, bound)| {
+            *bound != binding && !(*bound_action == action && bound.is_key() == binding.is_key())
+        });
+        self.bindings.push((action, binding));
+    }
+
+    pub fn describe(&self, action: Action) -> String {
+        self.bindings.iter()
+            .filter(|(bound_action, _)| *bound_action == action)
+            .map(|(_, binding)| binding.to_string())
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    // Passes a key / gamepad event on to the controller. Returns true if the event was bound to an action.
+    pub fn handle_event(&self, event: &Event, controller: &mut Controller) -> bool {
+        let mut handled = false;
+        for (action, binding) in self.bindings.iter() {
+            let pressed = match (event, binding) {
+                // Key repeats would restart macros, and the key is already held anyway
+                (Event::KeyDown { keycode: Some(keycode), repeat: true, .. }, Binding::Key(bound)) if keycode == bound => {
+                    handled = true;
+                    continue;
+                },
+                (Event::KeyDown { keycode: Some(keycode), .. }, Binding::Key(bound)) if keycode == bound => true,
+                (Event::KeyUp { keycode: Some(keycode), .. }, Binding::Key(bound)) if keycode == bound => false,
+                (Event::ControllerButtonDown { button: pad, .. }, Binding::Pad(bound)) if pad == bound => true,
+                (Event::ControllerButtonUp { button: pad, .. }, Binding::Pad(bound)) if pad == bound => false,
+                (Event::ControllerAxisMotion { axis, value, .. }, Binding::Axis(bound, positive)) if axis == bound => {
+                    if *positive { *value > AXIS_THRESHOLD } else { *value < -AXIS_THRESHOLD }
+                },
+                _ => continue,
+            };
+            controller.press(*action, pressed);
+            handled = true;
+        }
+        handled
+    }
+}
+
+/////////////////////////////// CONTROLLER ////////////////////////////////
+
+// Turns what the player is holding into the InputStates for each frame, adding autofire and macros.
+// Macros are per-frame recordings of the joypad, saved as one packed byte per frame in <macro_dir>/macroN.bin
+pub struct Controller {
+    held: u8, // packed like InputStates::pack
+    turbo: u8, // held turbo buttons
+    pub turbo_rate: f64, // presses per second
+    frame: u64,
+
+    pub macro_dir: PathBuf,
+    pub macros: Vec<Vec<u8>>,
+    playing: Option<(usize, usize)>, // (slot, frame)
+    recording: Option<Vec<u8>>,
+}
+
+impl Controller {
+    pub fn new(macro_dir: &Path, turbo_rate: f64) -> Self {
+        let macros = (1..=MACRO_SLOTS)
+            .map(|slot| fs::read(Self::macro_path(macro_dir, slot)).unwrap_or_default())
+            .collect();
+        Controller {
+            held: 0,
+            turbo: 0,
+            turbo_rate,
+            frame: 0,
+
+            macro_dir: macro_dir.to_path_buf(),
+            macros,
+            playing: None,
+            recording: None,
+        }
+    }
+
+    fn macro_path(macro_dir: &Path, slot: usize) -> PathBuf {
+        macro_dir.join(format!("macro{}.bin", slot))
+    }
+
+    pub fn press(&mut self, action: Action, pressed: bool) {
+        match action {
+            Action::Button(button) => {
+                if pressed { self.held |= button.mask() } else { self.held &= !button.mask() }
+            },
+            Action::Turbo(button) => {
+                if pressed { self.turbo |= button.mask() } else { self.turbo &= !button.mask() }
+            },
+            Action::Macro(slot) if pressed => match self.recording.take() {
+                Some(recording) => self.save_macro(slot, recording),
+                None => {
+                    if self.macros[slot - 1].is_empty() {
+                        println!("MACRO {} IS EMPTY", slot);
+                    } else { self.playing = Some((slot, 0)); }
+                },
+            },
+            Action::Macro(_) => {},
+        }
+    }
+
+    // Starts recording, or throws away the recording in progress. A recording is stored by pressing a macro key.
+    pub fn toggle_recording(&mut self) {
+        self.recording = match self.recording {
+            Some(_) => { println!("MACRO RECORDING CANCELLED"); None },
+            None => { println!("RECORDING MACRO, PRESS A MACRO KEY TO STORE IT"); Some(Vec::new()) },
+        };
+    }
+
+    fn save_macro(&mut self, slot: usize, recording: Vec<u8>) {
+        let path = Self::macro_path(&self.macro_dir, slot);
+        let saved = fs::create_dir_all(&self.macro_dir).and_then(|_| fs::write(&path, &recording));
+        match saved {
+            Ok(()) => println!("MACRO {} SAVED ({} FRAMES)", slot, recording.len()),
+            Err(e) => println!("UNABLE TO SAVE {}: {}", path.display(), e),
+        }
+        self.macros[slot - 1] = recording;
+    }
+
+    // Called once per frame. A playing macro overrides the player's input until it runs out.
+    pub fn apply(&mut self, inputs: &mut InputStates) {
+        self.frame += 1;
+        let mut packed = self.held;
+        let period = ((CYCLES_PER_SECOND as f64 / CYCLES_PER_FRAME as f64) / self.turbo_rate).round().max(2.0) as u64;
+        if self.frame % period < period / 2 {
+            packed |= self.turbo;
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(packed);
+        }
+        if let Some((slot, frame)) = self.playing {
+            packed = self.macros[slot - 1][frame];
+            self.playing = if frame + 1 < self.macros[slot - 1].len() { Some((slot, frame + 1)) } else { None };
+        }
+        inputs.unpack(packed);
+    }
+}
+
+/////////////////////////////// GAMEPADS ////////////////////////////////
+
+// Keeps connected controllers open, SDL only sends their events while they are
+pub struct Gamepads {
+    subsystem: Option<GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+}
+
+impl Gamepads {
+    pub fn new(sdl_context: &Sdl) -> Self {
+        let subsystem = sdl_context.game_controller()
+            .map_err(|e| println!("GAMEPADS UNAVAILABLE: {}", e))
+            .ok();
+        Gamepads {
+            subsystem,
+            controllers: Vec::new(),
+        }
+    }
+
+    // Opens / closes controllers as they are plugged in. SDL also reports controllers that were
+    // already connected as added when the subsystem starts.
+    pub fn handle_event(&mut self, event: &Event) {
+        let subsystem = match self.subsystem.as_ref() {
+            Some(subsystem) => subsystem,
+            None => return,
+        };
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => match subsystem.open(*which) {
+                Ok(controller) => {
+                    println!("GAMEPAD CONNECTED: {}", controller.name());
+                    self.controllers.push(controller);
+                },
+                Err(e) => println!("UNABLE TO OPEN GAMEPAD: {}", e),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers.retain(|controller| controller.instance_id() != *which as i32);
+            },
+            _ => {},
+        }
+    }
+}
+
+// Waits up to timeout for a button press or a pushed stick on any gamepad and returns it as a binding.
+// Used by the TUI to rebind gamepad inputs, before the emulator has set SDL up.
+pub fn wait_for_pad_input(timeout: Duration) -> Option<Binding> {
+    let sdl_context = sdl2::init().ok()?;
+    let mut gamepads = Gamepads::new(&sdl_context);
+    let mut event_pump = sdl_context.event_pump().ok()?;
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        for event in event_pump.poll_iter() {
+            gamepads.handle_event(&event);
+            match event {
+                Event::ControllerButtonDown { button, .. } => return Some(Binding::Pad(button)),
+                Event::ControllerAxisMotion { axis, value, .. } if value > AXIS_THRESHOLD => return Some(Binding::Axis(axis, true)),
+                Event::ControllerAxisMotion { axis, value, .. } if value < -AXIS_THRESHOLD => return Some(Binding::Axis(axis, false)),
+                _ => {},
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    None
+}
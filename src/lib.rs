@@ -3,4 +3,27 @@ pub mod memory;
 pub mod registers;
 pub mod ppu;
 pub mod timer;
-pub mod apu;
\ No newline at end of file
+pub mod apu;
+pub mod disassembler;
+pub mod debugger;
+pub mod gdb;
+pub mod symbols;
+pub mod viewer;
+pub mod pacer;
+pub mod savestate;
+pub mod rewind;
+pub mod crc32;
+pub mod movie;
+pub mod input;
+pub mod cli;
+pub mod screenshot;
+pub mod storage;
+pub mod accounts;
+pub mod scores;
+pub mod history;
+pub mod achievements;
+pub mod cartridge;
+pub mod library;
+pub mod archive;
+pub mod patch;
+pub mod palette;
@@ -0,0 +1,222 @@
+use crate::archive;
+use crate::cartridge::{CartridgeHeader, HEADER_SIZE};
+use crate::crc32::crc32;
+use crate::patch::{patch_for_rom, read_patched_rom};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The rom library. Every library folder is scanned recursively for .gb and .gbc files, and for .zip and
+// .gz archives holding one (archives without a rom are left out). Roms are listed as they're played, with
+// their patch (see patch.rs) applied. Hashing a rom and checking its global checksum means reading all of
+// it, so the results are cached in an index file and only redone for files whose size or modification
+// time changed, or whose patch was added, removed or changed. Index rows, tab separated:
+//
+//   crc32 | size | modified | patch modified | computed global checksum | title | licensee | cartridge type | path
+//
+// Rows from an older index with fewer columns are dropped, so those roms are simply indexed again.
+
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub name: String, // relative to its library folder
+    pub hash: u32,
+    pub size: u64,
+    pub modified: u64, // seconds since the unix epoch
+    pub patch_modified: u64, // 0 without a patch
+    pub title: String, // empty if the header couldn't be read, like licensee and cartridge_type
+    pub licensee: String,
+    pub cartridge_type: String,
+    pub computed_global_checksum: u16,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SortBy {
+    Title,
+    Licensee,
+    CartridgeType,
+    LastPlayed,
+}
+
+impl SortBy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortBy::Title => "title",
+            SortBy::Licensee => "licensee",
+            SortBy::CartridgeType => "cartridge type",
+            SortBy::LastPlayed => "last played",
+        }
+    }
+
+    pub fn next(&self) -> SortBy {
+        match self {
+            SortBy::Title => SortBy::Licensee,
+            SortBy::Licensee => SortBy::CartridgeType,
+            SortBy::CartridgeType => SortBy::LastPlayed,
+            SortBy::LastPlayed => SortBy::Title,
+        }
+    }
+}
+
+impl RomEntry {
+    // Only the header is read again, the global checksum comes from the index. Archives have to be unpacked
+    // and patches applied.
+    pub fn header(&self) -> io::Result<CartridgeHeader> {
+        let mut buffer = Vec::with_capacity(HEADER_SIZE);
+        if archive::is_archive(&self.path) || patch_for_rom(&self.path).is_some() {
+            buffer = read_patched_rom(&self.path)?;
+        } else {
+            fs::File::open(&self.path)?.take(HEADER_SIZE as u64).read_to_end(&mut buffer)?;
+        }
+        let mut header = CartridgeHeader::parse(&buffer, false)?;
+        header.computed_global_checksum = Some(self.computed_global_checksum);
+        Ok(header)
+    }
+}
+
+pub struct Library {
+    pub dirs: Vec<PathBuf>,
+    pub index_path: PathBuf,
+    pub roms: Vec<RomEntry>, // sorted by name until sort is called
+}
+
+impl Library {
+    pub fn new(dirs: Vec<PathBuf>, index_path: PathBuf) -> Self {
+        Library {
+            dirs,
+            index_path,
+            roms: Vec::new(),
+        }
+    }
+
+    pub fn scan(&mut self) -> io::Result<()> {
+        let mut cached = self.load_index();
+        self.roms.clear();
+        for dir in self.dirs.clone() {
+            let mut files = Vec::new();
+            find_roms(&dir, &mut files);
+            for path in files {
+                let metadata = match fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+                let size = metadata.len();
+                let modified = seconds(metadata.modified());
+                let patch_modified = patch_for_rom(&path).and_then(|patch| fs::metadata(patch).ok()).map_or(0, |metadata| seconds(metadata.modified()));
+                let name = path.strip_prefix(&dir).unwrap_or(&path).to_string_lossy().to_string();
+
+                let unchanged = cached.iter().position(|entry| {
+                    entry.path == path && entry.size == size && entry.modified == modified && entry.patch_modified == patch_modified
+                });
+                let entry = match unchanged {
+                    Some(index) => RomEntry { name, ..cached.swap_remove(index) },
+                    None => match index_rom(&path, name, size, modified, patch_modified) {
+                        Ok(entry) => entry,
+                        Err(_) => continue,
+                    },
+                };
+                self.roms.push(entry);
+            }
+        }
+        self.roms.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        self.save_index()
+    }
+
+    // Favourites first, then by the chosen field. Roms without a title go by their file name, and
+    // last_played (header title to the time the game was last played) puts the most recent first.
+    pub fn sort(&mut self, by: SortBy, favourites: &[u32], last_played: &HashMap<String, i64>) {
+        let title = |entry: &RomEntry| if entry.title.is_empty() { entry.name.to_lowercase() } else { entry.title.to_lowercase() };
+        self.roms.sort_by(|a, b| {
+            let favourite = favourites.contains(&b.hash).cmp(&favourites.contains(&a.hash));
+            let field = match by {
+                SortBy::Title => title(a).cmp(&title(b)),
+                SortBy::Licensee => a.licensee.cmp(&b.licensee),
+                SortBy::CartridgeType => a.cartridge_type.cmp(&b.cartridge_type),
+                SortBy::LastPlayed => last_played.get(&b.title).cmp(&last_played.get(&a.title)),
+            };
+            favourite.then(field).then_with(|| title(a).cmp(&title(b)))
+        });
+    }
+
+    pub fn find(&self, hash: u32) -> Option<&RomEntry> {
+        self.roms.iter().find(|entry| entry.hash == hash)
+    }
+
+    // A missing or damaged index just means everything gets hashed again
+    fn load_index(&self) -> Vec<RomEntry> {
+        let text = match fs::read_to_string(&self.index_path) {
+            Ok(text) => text,
+            Err(_) => return Vec::new(),
+        };
+        text.lines().filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(9, '\t').collect();
+            if fields.len() != 9 {
+                return None;
+            }
+            Some(RomEntry {
+                path: PathBuf::from(fields[8]),
+                name: String::new(),
+                hash: u32::from_str_radix(fields[0], 16).ok()?,
+                size: fields[1].parse().ok()?,
+                modified: fields[2].parse().ok()?,
+                patch_modified: fields[3].parse().ok()?,
+                title: fields[5].to_string(),
+                licensee: fields[6].to_string(),
+                cartridge_type: fields[7].to_string(),
+                computed_global_checksum: u16::from_str_radix(fields[4], 16).ok()?,
+            })
+        }).collect()
+    }
+
+    fn save_index(&self) -> io::Result<()> {
+        let text: String = self.roms.iter().map(|entry| {
+            format!("{:08x}\t{}\t{}\t{}\t{:04x}\t{}\t{}\t{}\t{}\n", entry.hash, entry.size, entry.modified, entry.patch_modified, entry.computed_global_checksum,
+                    entry.title, entry.licensee, entry.cartridge_type, entry.path.display())
+        }).collect();
+        fs::write(&self.index_path, text)
+    }
+}
+
+fn is_rom(path: &Path) -> bool {
+    archive::is_rom_name(&path.to_string_lossy()) || archive::is_archive(path)
+}
+
+// Symlinked folders aren't followed, so a link back up the tree can't loop forever
+fn find_roms(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => { println!("UNABLE TO READ {}: {}", dir.display(), e); return; },
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => find_roms(&path, files),
+            Ok(_) if is_rom(&path) && !path.to_string_lossy().contains(['\t', '\n']) => files.push(path),
+            _ => {},
+        }
+    }
+}
+
+fn seconds(time: io::Result<SystemTime>) -> u64 {
+    time.ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |time| time.as_secs())
+}
+
+fn index_rom(path: &Path, name: String, size: u64, modified: u64, patch_modified: u64) -> io::Result<RomEntry> {
+    let rom = read_patched_rom(path)?;
+    let header = CartridgeHeader::parse(&rom, true).ok();
+    Ok(RomEntry {
+        path: path.to_path_buf(),
+        name,
+        hash: crc32(&rom),
+        size,
+        modified,
+        patch_modified,
+        title: header.as_ref().map_or(String::new(), |header| header.title.clone()),
+        licensee: header.as_ref().map_or(String::new(), |header| header.licensee.clone()),
+        cartridge_type: header.as_ref().map_or(String::new(), |header| header.type_name().to_string()),
+        computed_global_checksum: header.and_then(|header| header.computed_global_checksum).unwrap_or(0),
+    })
+}
@@ -1,7 +1,9 @@
-use std::fs::File;
-use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::Result;
+use std::path::Path;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::patch::read_patched_rom;
 
 const KIB:usize = 1024;
 
@@ -32,7 +34,8 @@ pub struct Memory{
     // FEA0 -> FEFF Unusable
     pub io_registers: Box<[u8; 0xFF7F - 0xFF00 + 1]>, // FF00 -> FF7F | I/O Registers
     pub hram: Box<[u8; 0xFFFE - 0xFF80 + 1]>, // FF80 -> FFFE | High RAM
-    pub ie_register: Box<[u8; 1]> // FFFF -> FFFF | Interrupt enable register (IE)
+    pub ie_register: Box<[u8; 1]>, // FFFF -> FFFF | Interrupt enable register (IE)
+    pub boot_rom: Option<Box<[u8; 256]>>, // 0000 -> 00FF | Mapped over the cartridge until FF50 is written to
 }
 
 impl Memory{
@@ -53,14 +56,21 @@ impl Memory{
             oam: box_arr![0; 0xFE9F - 0xFE00 + 1],
             io_registers: box_arr![0; 0xFF7F - 0xFF00 + 1], // Might need to un array this as io registers can have special behaviour
             hram: box_arr![0; 0xFFFE - 0xFF80 + 1],
-            ie_register: box_arr![0; 1] 
+            ie_register: box_arr![0; 1],
+            boot_rom: None,
         }
     }
 
-    pub fn load_rom(&mut self, filename:&str){
-        let mut f = File::open(filename).expect("Unable to open file!");
-        let mut buffer = vec![0_u8; 512*KIB];
-        f.read(&mut buffer);
+    pub fn load_rom(&mut self, filename:&str) -> Result<()>{
+        // Zipped and gzipped roms are unpacked and patches applied first
+        let rom = read_patched_rom(Path::new(filename))?;
+        self.load_rom_data(&rom);
+        Ok(())
+    }
+
+    pub fn load_rom_data(&mut self, rom:&[u8]){
+        let mut buffer = rom.to_vec();
+        buffer.resize(512*KIB, 0);
 
         for i in 0..(32*KIB){
             if i < (16*KIB){
@@ -69,6 +79,28 @@ impl Memory{
         }
     }
 
+    // Battery backed cartridge RAM. Only the one bank at A000 is mapped so far, so that's all that's kept.
+    pub fn load_battery_ram(&mut self, path: &Path) -> Result<()> {
+        let data = std::fs::read(path)?;
+        let length = data.len().min(8*KIB);
+        self.extern_ram[..length].copy_from_slice(&data[..length]);
+        Ok(())
+    }
+
+    pub fn save_battery_ram(&self, path: &Path, size: usize) -> Result<()> {
+        std::fs::write(path, &self.extern_ram[..size.min(8*KIB)])
+    }
+
+    pub fn load_boot_rom(&mut self, filename: &str) -> Result<()> {
+        let data = std::fs::read(filename)?;
+        if data.len() != 256 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "a DMG boot rom is 256 bytes"));
+        }
+        self.boot_rom = Some(box_arr![0; 256]);
+        self.boot_rom.as_mut().unwrap().copy_from_slice(&data);
+        Ok(())
+    }
+
 /*
     pub fn load_rom(&mut self, filename:& str) -> Result<()> {
         let mut f = BufReader::new(File::open(filename)?);
@@ -106,6 +138,7 @@ impl Memory{
                 print!("{}", (data as u8) as char)
             } else { self.io_registers[address as usize - 0xFF00] = data; } },
             0xFF46 => { self.dma_transfer(data); },
+            0xFF50 => { if data != 0 { self.boot_rom = None; } self.io_registers[0x50] = data; },
             0xFF00..=0xFF7F => { self.io_registers[address as usize - 0xFF00] = data; /*if address == 0xFF41 && (data & 0b0000_0100) == 0 { println!("STAT => {:#010b}", data); }*/ },
             0xFF80..=0xFFFE => { self.hram[address as usize - 0xFF80] = data },
             0xFFFF => { /*println!( "IE WRITTEN TO => {:#010b}", data);*/ self.ie_register[0] = data },
@@ -119,6 +152,7 @@ impl Memory{
         // } 
         //else { 0 };
         let data = match address {
+            0..=0xFF if self.boot_rom.is_some() => self.boot_rom.as_ref().unwrap()[address as usize],
             0..=0x3FFF => self.rom_bank_0[address as usize],
             0x4000..=0x7FFF => self.rom_bank_n[(address as usize - 0x4000)],
             0x8000..=0x9FFF => self.vram[address as usize - 0x8000],
@@ -135,6 +169,31 @@ impl Memory{
         return data;
     }
 
+    // Everything writable - the rom banks aren't included as they come from the cartridge file
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.vram[..]);
+        state.bytes(&self.extern_ram[..]);
+        state.bytes(&self.ram_bank_0[..]);
+        state.bytes(&self.ram_bank_1[..]);
+        state.bytes(&self.mirror[..]);
+        state.bytes(&self.oam[..]);
+        state.bytes(&self.io_registers[..]);
+        state.bytes(&self.hram[..]);
+        state.bytes(&self.ie_register[..]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) {
+        state.bytes(&mut self.vram[..]);
+        state.bytes(&mut self.extern_ram[..]);
+        state.bytes(&mut self.ram_bank_0[..]);
+        state.bytes(&mut self.ram_bank_1[..]);
+        state.bytes(&mut self.mirror[..]);
+        state.bytes(&mut self.oam[..]);
+        state.bytes(&mut self.io_registers[..]);
+        state.bytes(&mut self.hram[..]);
+        state.bytes(&mut self.ie_register[..]);
+    }
+
     pub fn dma_transfer(&mut self, address: u8) {
         // println!("INIT DMA FROM => {:x}", address);
         let val = match address {
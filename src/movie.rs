@@ -0,0 +1,170 @@
+use crate::patch::read_patched_rom;
+use crate::cpu::InputStates;
+use crate::crc32::crc32;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Input movies. A movie is the joypad state for every frame, plus what's needed to replay it:
+// the crc32 of the ROM it was made with and, unless it starts from power on, the save state it starts from.
+// Each frame also stores the crc32 of the finished frame's displaybuffer so playback can report the
+// first frame that differs. The displaybuffer is in host colours, so play back with the same palette.
+//
+// File layout (little endian):
+//   "NMOV" | version u8 | rom crc32 u32 | state length u32 (0 => power on) | state | frames...
+//   frame: inputs u8 (down, up, left, right, start, select, b, a from bit 7 to 0) | screen crc32 u32
+
+const MAGIC: &[u8; 4] = b"NMOV";
+const VERSION: u8 = 1;
+
+#[derive(Copy, Clone)]
+pub struct Frame {
+    pub inputs: u8,
+    pub screen: u32,
+}
+
+pub struct Movie {
+    pub rom_hash: u32,
+    pub start_state: Option<Vec<u8>>,
+    pub frames: Vec<Frame>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u32, start_state: Option<Vec<u8>>) -> Self {
+        Movie {
+            rom_hash,
+            start_state,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        if data.len() < 13 || &data[0..4] != MAGIC {
+            return Err(invalid("not a movie file"));
+        }
+        if data[4] != VERSION {
+            return Err(invalid("movie was made by a different version"));
+        }
+        let rom_hash = u32::from_le_bytes([data[5], data[6], data[7], data[8]]);
+        let state_len = u32::from_le_bytes([data[9], data[10], data[11], data[12]]) as usize;
+        let frames_start = 13 + state_len;
+        if data.len() < frames_start || (data.len() - frames_start) % 5 != 0 {
+            return Err(invalid("movie file is truncated"));
+        }
+        let start_state = if state_len > 0 { Some(data[13..frames_start].to_vec()) } else { None };
+        let frames = data[frames_start..].chunks(5).map(|frame| Frame {
+            inputs: frame[0],
+            screen: u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]),
+        }).collect();
+
+        Ok(Movie { rom_hash, start_state, frames })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let state: &[u8] = self.start_state.as_deref().unwrap_or(&[]);
+        let mut data = Vec::with_capacity(13 + state.len() + self.frames.len() * 5);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        data.extend_from_slice(state);
+        for frame in self.frames.iter() {
+            data.push(frame.inputs);
+            data.extend_from_slice(&frame.screen.to_le_bytes());
+        }
+        fs::write(path, data)
+    }
+}
+
+// <rom>.nmv, alongside the rom like its .sym file
+pub fn path_for_rom(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("nmv")
+}
+
+pub fn rom_hash(rom_path: &str) -> io::Result<u32> {
+    Ok(crc32(&read_patched_rom(Path::new(rom_path))?))
+}
+
+/////////////////////////////// RECORDING ////////////////////////////////
+
+// Both halves of a frame are driven from the main loop's frame boundary:
+// end_frame for the frame that just finished, then start_frame with the inputs for the next one.
+pub struct Recorder {
+    pub movie: Movie,
+    pub path: PathBuf,
+    in_frame: bool,
+}
+
+impl Recorder {
+    pub fn new(movie: Movie, path: PathBuf) -> Self {
+        Recorder {
+            movie,
+            path,
+            in_frame: false,
+        }
+    }
+
+    pub fn start_frame(&mut self, inputs: &InputStates) {
+        self.movie.frames.push(Frame { inputs: inputs.pack(), screen: 0 });
+        self.in_frame = true;
+    }
+
+    pub fn end_frame(&mut self, displaybuffer: &[u8]) {
+        if self.in_frame {
+            self.movie.frames.last_mut().unwrap().screen = crc32(displaybuffer);
+            self.in_frame = false;
+        }
+    }
+
+    // Saves the movie, dropping a frame that was started but never finished
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        if self.in_frame {
+            self.movie.frames.pop();
+        }
+        self.movie.save(&self.path)?;
+        Ok(self.path)
+    }
+}
+
+/////////////////////////////// PLAYBACK ////////////////////////////////
+
+pub struct Player {
+    pub movie: Movie,
+    pub frame: usize, // the frame currently being played
+    pub desynced_at: Option<usize>,
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Self {
+        Player {
+            movie,
+            frame: 0,
+            desynced_at: None,
+        }
+    }
+
+    // Overwrites the inputs with the next frame's. Returns false once the movie is over.
+    pub fn start_frame(&mut self, inputs: &mut InputStates) -> bool {
+        match self.movie.frames.get(self.frame) {
+            Some(frame) => { inputs.unpack(frame.inputs); true },
+            None => false,
+        }
+    }
+
+    // Checks the finished frame against the recording. Returns false on the first frame that differs.
+    pub fn end_frame(&mut self, displaybuffer: &[u8]) -> bool {
+        let matches = match self.movie.frames.get(self.frame) {
+            Some(frame) => frame.screen == crc32(displaybuffer),
+            None => return true,
+        };
+        self.frame += 1;
+        if !matches && self.desynced_at.is_none() {
+            self.desynced_at = Some(self.frame - 1);
+            return false;
+        }
+        true
+    }
+}
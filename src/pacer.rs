@@ -0,0 +1,84 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Frame pacing. Real time is derived from emulated cycles (4194304 per second, 70224 per frame,
+// so ~59.73 frames a second) and the emulator sleeps until the host clock catches up.
+// The pacer never spins - every wait is a thread::sleep.
+
+pub const CYCLES_PER_SECOND: u64 = 4_194_304;
+pub const CYCLES_PER_FRAME: u64 = 70_224;
+
+// How much audio we are happy to have queued when pacing off the audio device
+const AUDIO_LATENCY: Duration = Duration::from_millis(50);
+// If the host falls further behind than this we stop trying to catch up and resync
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+pub struct Pacer {
+    start: Instant,
+    start_cycles: u64,
+    last_speed: f64,
+
+    pub fast_forward: bool,
+    pub slow_motion: bool,
+    pub fast_forward_multiplier: f64, // 0 => run unthrottled
+    pub slow_motion_multiplier: f64,
+}
+
+impl Pacer {
+    pub fn new(fast_forward_multiplier: f64, slow_motion_multiplier: f64) -> Self {
+        Pacer {
+            start: Instant::now(),
+            start_cycles: 0,
+            last_speed: 1.0,
+
+            fast_forward: false,
+            slow_motion: false,
+            fast_forward_multiplier,
+            slow_motion_multiplier,
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        if self.fast_forward {
+            self.fast_forward_multiplier
+        } else if self.slow_motion {
+            self.slow_motion_multiplier
+        } else { 1.0 }
+    }
+
+    // Called once per emulated frame with the cpu's total cycle count.
+    // audio_queued is how much sound is waiting in the output device, if sound is on. When it is,
+    // the audio device's clock drives pacing at normal speed, so audio never under / over runs.
+    pub fn sync(&mut self, cycles: u64, audio_queued: Option<Duration>) {
+        let speed = self.speed();
+        if speed != self.last_speed {
+            self.reset(cycles);
+            self.last_speed = speed;
+        }
+        if speed <= 0.0 {
+            return;
+        }
+
+        if let (Some(queued), false, false) = (audio_queued, self.fast_forward, self.slow_motion) {
+            if queued > AUDIO_LATENCY {
+                thread::sleep(queued - AUDIO_LATENCY);
+            }
+            self.reset(cycles);
+            return;
+        }
+
+        let emulated = Duration::from_secs_f64((cycles - self.start_cycles) as f64 / CYCLES_PER_SECOND as f64 / speed);
+        let target = self.start + emulated;
+        let now = Instant::now();
+        if target > now {
+            thread::sleep(target - now);
+        } else if now - target > MAX_LAG {
+            self.reset(cycles); // the host stalled (debugger, window drag...), don't fast-forward to catch up
+        }
+    }
+
+    fn reset(&mut self, cycles: u64) {
+        self.start = Instant::now();
+        self.start_cycles = cycles;
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Colour palettes: the four shades, lightest first, that the 2 bit colours from BGP, OBP0 and OBP1 are
+// drawn in. Like the CGB's palettes for DMG games, background, OBJ0 and OBJ1 sprites can differ.
+// Besides the built in ones, each <PALETTE_DIR>/<name>.cfg file is a palette, taking over from a built in
+// one with the same name:
+//
+//   bg = FFFFFF A9A9A9 545454 000000     # RRGGBB, lightest first
+//   obj0 = FFFFFF FF8484 943A3A 000000   # optional, obj0 and obj1 default to the bg shades
+//   obj1 = FFFFFF 7BFF31 0063C5 000000
+
+pub type Shades = [[u8; 3]; 4]; // RGB
+
+#[derive(Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+// The original four tints
+const BUILT_IN: [(&str, Shades); 4] = [
+    ("Grayscale", [[255, 255, 255], [169, 169, 169], [84, 84, 84], [0, 0, 0]]),
+    ("Redscale", [[188, 15, 15], [172, 15, 15], [98, 48, 48], [56, 15, 15]]),
+    ("Bluescale", [[15, 15, 188], [15, 15, 172], [48, 48, 98], [15, 15, 56]]),
+    ("Greenscale", [[15, 188, 155], [15, 172, 139], [48, 98, 48], [15, 56, 15]]),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::uniform(BUILT_IN[0].0, BUILT_IN[0].1)
+    }
+}
+
+impl Palette {
+    // The same shades for every layer
+    pub fn uniform(name: &str, shades: Shades) -> Self {
+        Palette { name: name.to_string(), bg: shades, obj0: shades, obj1: shades }
+    }
+
+    pub fn built_in() -> Vec<Palette> {
+        BUILT_IN.iter().map(|(name, shades)| Palette::uniform(name, *shades)).collect()
+    }
+
+    // The built in palettes in their original order, Grayscale (the default) first, then the rest of
+    // PALETTE_DIR's sorted by name. A file named like a built in palette replaces it in place.
+    pub fn all() -> Vec<Palette> {
+        let mut palettes = Palette::built_in();
+        let mut files = load_dir(&palette_dir());
+        files.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        for palette in files {
+            match palettes.iter().position(|other| other.name.eq_ignore_ascii_case(&palette.name)) {
+                Some(index) => palettes[index] = palette,
+                None => palettes.push(palette),
+            }
+        }
+        palettes
+    }
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let name = if name.eq_ignore_ascii_case("greyscale") { "grayscale" } else { name };
+        Palette::all().into_iter().find(|palette| palette.name.eq_ignore_ascii_case(name))
+    }
+
+    // Shade for a 2 bit background or window colour, in displaybuffer byte order
+    pub fn rgb(&self, colour: u8) -> [u8; 3] {
+        display_order(self.bg[colour as usize])
+    }
+
+    // Shade for a 2 bit sprite colour from OBP0, or OBP1 if obp1 is set
+    pub fn obj_rgb(&self, obp1: bool, colour: u8) -> [u8; 3] {
+        display_order(if obp1 { self.obj1 } else { self.obj0 }[colour as usize])
+    }
+
+    pub fn parse(text: &str, name: &str) -> io::Result<Palette> {
+        let mut layers: [Option<Shades>; 3] = [None; 3];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, line));
+            let (layer, value) = line.split_once('=').ok_or_else(error)?;
+            let index = match layer.trim() {
+                "bg" => 0,
+                "obj0" => 1,
+                "obj1" => 2,
+                _ => return Err(error()),
+            };
+            let colours: Vec<&str> = value.split_whitespace().collect();
+            if colours.len() != 4 {
+                return Err(error());
+            }
+            let mut shades: Shades = [[0; 3]; 4];
+            for (shade, colour) in shades.iter_mut().zip(colours) {
+                let colour = u32::from_str_radix(colour, 16).ok().filter(|_| colour.len() == 6).ok_or_else(error)?;
+                *shade = [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8];
+            }
+            layers[index] = Some(shades);
+        }
+        let bg = layers[0].ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no bg line"))?;
+        Ok(Palette { name: name.to_string(), bg, obj0: layers[1].unwrap_or(bg), obj1: layers[2].unwrap_or(bg) })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Palette> {
+        let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+        Palette::parse(&fs::read_to_string(path)?, &name)
+    }
+
+    pub fn to_text(&self) -> String {
+        let line = |layer: &str, shades: &Shades| {
+            let colours: Vec<String> = shades.iter().map(|[r, g, b]| format!("{:02X}{:02X}{:02X}", r, g, b)).collect();
+            format!("{} = {}\n", layer, colours.join(" "))
+        };
+        line("bg", &self.bg) + &line("obj0", &self.obj0) + &line("obj1", &self.obj1)
+    }
+
+    // Writes <PALETTE_DIR>/<name>.cfg, returning where
+    pub fn save(&self) -> io::Result<PathBuf> {
+        let dir = palette_dir();
+        fs::create_dir_all(&dir)?;
+        let name: String = self.name.chars().map(|c| if c == '/' || c == '\\' || c == ':' { '_' } else { c }).collect();
+        let path = dir.join(format!("{}.cfg", name));
+        fs::write(&path, self.to_text())?;
+        Ok(path)
+    }
+}
+
+fn display_order([r, g, b]: [u8; 3]) -> [u8; 3] {
+    [b, g, r]
+}
+
+pub fn palette_dir() -> PathBuf {
+    PathBuf::from(std::env::var("PALETTE_DIR").unwrap_or_else(|_| String::from("palettes")))
+}
+
+// A missing folder has no palettes; broken files are reported and skipped
+fn load_dir(dir: &Path) -> Vec<Palette> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut palettes = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().map_or(true, |extension| extension != "cfg") {
+            continue;
+        }
+        match Palette::load(&path) {
+            Ok(palette) => palettes.push(palette),
+            Err(e) => println!("IGNORING PALETTE {}: {}", path.display(), e),
+        }
+    }
+    palettes
+}
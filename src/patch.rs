@@ -0,0 +1,431 @@
+use crate::archive::{self, read_rom, MAX_ROM_SIZE};
+use crate::crc32::crc32;
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Soft-patching. A .ips, .bps or .ups file with the rom's base name (game.gb, game.gb.zip -> game.ips)
+// is applied to the rom in memory whenever it's loaded, so translations and romhacks run without touching
+// the original dump. If there are several, the first of IPS, BPS, UPS wins.
+//
+// IPS is a list of (3 byte offset, 2 byte size, data) records, size 0 meaning a 2 byte run length and a
+// fill byte, ended by "EOF". UPS XORs runs of bytes at varint-encoded gaps. BPS builds the target from
+// copies out of the source, the patch and the target so far. UPS and BPS end with the crc32s of the
+// source, the target and the patch, which are all checked.
+
+pub const EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+pub fn patch_for_rom(rom_path: &Path) -> Option<PathBuf> {
+    let mut base = rom_path.with_extension("");
+    if archive::is_archive(rom_path) && archive::is_rom_name(&base.to_string_lossy()) {
+        base = base.with_extension("");
+    }
+    EXTENSIONS.iter().map(|extension| {
+        let mut path = OsString::from(base.as_os_str());
+        path.push(".");
+        path.push(extension);
+        PathBuf::from(path)
+    }).find(|path| path.exists())
+}
+
+// The rom as it's played, with its patch applied if it has one
+pub fn read_patched_rom(rom_path: &Path) -> io::Result<Vec<u8>> {
+    let rom = read_rom(rom_path)?;
+    match patch_for_rom(rom_path) {
+        Some(path) => apply(rom, &fs::read(&path)?).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+        None => Ok(rom),
+    }
+}
+
+pub fn apply(rom: Vec<u8>, patch: &[u8]) -> io::Result<Vec<u8>> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(&rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(&rom, patch)
+    } else {
+        Err(invalid("not an IPS, UPS or BPS patch"))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn truncated() -> io::Error {
+    invalid("truncated patch")
+}
+
+// Reads patch bytes in order
+struct PatchReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = *self.data.get(self.position).ok_or_else(truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + length).ok_or_else(truncated)?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn big_endian(&mut self, length: usize) -> io::Result<usize> {
+        Ok(self.bytes(length)?.iter().fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    // UPS and BPS numbers: 7 bits at a time, least significant first, the top bit marking the last byte.
+    // Each continuation also adds one so every number has a single encoding.
+    fn varint(&mut self) -> io::Result<usize> {
+        let (mut value, mut shift) = (0_usize, 1_usize);
+        loop {
+            let byte = self.byte()?;
+            value = value.checked_add((byte & 0x7F) as usize * shift).ok_or_else(|| invalid("bad number in patch"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).filter(|&shift| shift < 1 << 48).ok_or_else(|| invalid("bad number in patch"))?;
+            value += shift;
+        }
+    }
+}
+
+/////////////////////////////////// IPS ///////////////////////////////////
+
+fn apply_ips(mut rom: Vec<u8>, patch: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = PatchReader { data: patch, position: 5 };
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == 0x454F46 { // "EOF"
+            break;
+        }
+        let size = reader.big_endian(2)?;
+        let (data, length) = match size {
+            0 => {
+                let length = reader.big_endian(2)?;
+                (None, length)
+            },
+            _ => (Some(reader.bytes(size)?), size),
+        };
+        if rom.len() < offset + length {
+            rom.resize(offset + length, 0);
+        }
+        match data {
+            Some(data) => rom[offset..offset + length].copy_from_slice(data),
+            None => { let fill = reader.byte()?; rom[offset..offset + length].fill(fill) },
+        }
+    }
+    // Some patchers add a 3 byte size to truncate to after EOF
+    if let Ok(size) = reader.big_endian(3) {
+        rom.truncate(size);
+    }
+    Ok(rom)
+}
+
+////////////////////////////// UPS AND BPS //////////////////////////////
+
+// The three crc32s at the end of a UPS or BPS patch. The patch's own crc covers everything before it.
+fn footer(patch: &[u8]) -> io::Result<(u32, u32)> {
+    if patch.len() < 16 {
+        return Err(truncated());
+    }
+    let crc_at = |offset: usize| u32::from_le_bytes([patch[offset], patch[offset + 1], patch[offset + 2], patch[offset + 3]]);
+    let end = patch.len() - 12;
+    if crc32(&patch[..patch.len() - 4]) != crc_at(end + 8) {
+        return Err(invalid("the patch is damaged, its crc doesn't match"));
+    }
+    Ok((crc_at(end), crc_at(end + 4)))
+}
+
+fn check_target(target: &[u8], crc: u32) -> io::Result<()> {
+    if crc32(target) != crc {
+        return Err(invalid("the patched rom's crc doesn't match"));
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let (source_crc, target_crc) = footer(patch)?;
+    if crc32(rom) != source_crc {
+        return Err(invalid("the patch is for a different rom"));
+    }
+    let end = patch.len() - 12;
+    let mut reader = PatchReader { data: &patch[..end], position: 4 };
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if target_size > MAX_ROM_SIZE {
+        return Err(invalid("the patched rom would be too big"));
+    }
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut position = 0;
+    while reader.position < end {
+        position += reader.varint()?;
+        loop {
+            let byte = reader.byte()?;
+            if let Some(target_byte) = target.get_mut(position) {
+                *target_byte ^= byte;
+            }
+            position += 1;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let (source_crc, target_crc) = footer(patch)?;
+    if crc32(rom) != source_crc {
+        return Err(invalid("the patch is for a different rom"));
+    }
+    let end = patch.len() - 12;
+    let mut reader = PatchReader { data: &patch[..end], position: 4 };
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if target_size > MAX_ROM_SIZE {
+        return Err(invalid("the patched rom would be too big"));
+    }
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    // Copies move a relative offset into the source or target, its lowest bit the sign
+    let relative = |position: usize, data: usize| -> io::Result<usize> {
+        let distance = data >> 1;
+        let moved = if data & 1 != 0 { position.checked_sub(distance) } else { position.checked_add(distance) };
+        moved.ok_or_else(|| invalid("bad copy in patch"))
+    };
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_position, mut target_position) = (0, 0);
+    while reader.position < end {
+        let data = reader.varint()?;
+        let length = (data >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err(invalid("patch writes past the end of the rom"));
+        }
+        match data & 3 {
+            0 => { // source read, the same bytes as the source
+                let start = target.len();
+                target.extend_from_slice(rom.get(start..start + length).ok_or_else(|| invalid("bad copy in patch"))?);
+            },
+            1 => target.extend_from_slice(reader.bytes(length)?), // target read, bytes from the patch
+            2 => { // source copy
+                source_position = relative(source_position, reader.varint()?)?;
+                target.extend_from_slice(rom.get(source_position..source_position + length).ok_or_else(|| invalid("bad copy in patch"))?);
+                source_position += length;
+            },
+            _ => { // target copy, byte by byte since it can overlap what it writes
+                target_position = relative(target_position, reader.varint()?)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_position).ok_or_else(|| invalid("bad copy in patch"))?;
+                    target.push(byte);
+                    target_position += 1;
+                }
+            },
+        }
+    }
+    if target.len() != target_size {
+        return Err(invalid("the patched rom is the wrong size"));
+    }
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = b"ABCDEFGH";
+
+    fn message(result: io::Result<Vec<u8>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+
+    // The three crc32s a UPS or BPS patch ends with
+    fn finish(mut patch: Vec<u8>, source: &[u8], target_crc: u32) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&target_crc.to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    fn ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        let byte_at = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+        let (mut i, mut position) = (0, 0);
+        while i < target.len() {
+            if byte_at(source, i) == target[i] {
+                i += 1;
+                continue;
+            }
+            patch.extend(varint(i - position));
+            while i < target.len() && byte_at(source, i) != target[i] {
+                patch.push(byte_at(source, i) ^ target[i]);
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            position = i;
+        }
+        finish(patch, source, crc32(target))
+    }
+
+    // ABCD from the source, xy from the patch, EF copied from the source, then EFEFE copied from the target
+    // as it's written
+    const BPS_TARGET: &[u8] = b"ABCDxyEFEFEFE";
+
+    fn bps_body(target_size: usize) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(varint(SOURCE.len()));
+        patch.extend(varint(target_size));
+        patch.extend(varint(0)); // no metadata
+        patch.extend(varint((4 - 1) << 2)); // source read
+        patch.extend(varint((2 - 1) << 2 | 1)); // target read
+        patch.extend_from_slice(b"xy");
+        patch.extend(varint((2 - 1) << 2 | 2)); // source copy from 4
+        patch.extend(varint(4 << 1));
+        patch.extend(varint((5 - 1) << 2 | 3)); // target copy from 6
+        patch.extend(varint(6 << 1));
+        patch
+    }
+
+    fn bps() -> Vec<u8> {
+        finish(bps_body(BPS_TARGET.len()), SOURCE, crc32(BPS_TARGET))
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 30] {
+            let bytes = varint(value);
+            assert_eq!(PatchReader { data: &bytes, position: 0 }.varint().unwrap(), value);
+        }
+        let endless = [0; 10];
+        assert_eq!(PatchReader { data: &endless, position: 0 }.varint().unwrap_err().to_string(), "bad number in patch");
+        assert_eq!(PatchReader { data: &endless[..3], position: 0 }.varint().unwrap_err().to_string(), "truncated patch");
+    }
+
+    #[test]
+    fn rejects_unknown_patches() {
+        assert_eq!(message(apply(SOURCE.to_vec(), b"")), "not an IPS, UPS or BPS patch");
+        assert_eq!(message(apply(SOURCE.to_vec(), b"PK\x03\x04")), "not an IPS, UPS or BPS patch");
+    }
+
+    #[test]
+    fn applies_ips() {
+        // Two bytes at 1, a run of four 'z' at 6 that grows the rom, then EOF
+        let patch = b"PATCH\x00\x00\x01\x00\x02xy\x00\x00\x06\x00\x00\x00\x04zEOF";
+        assert_eq!(apply(SOURCE.to_vec(), patch).unwrap(), b"AxyDEFzzzz");
+        // The same with a size to truncate to after EOF
+        let mut truncating = patch.to_vec();
+        truncating.extend_from_slice(b"\x00\x00\x05");
+        assert_eq!(apply(SOURCE.to_vec(), &truncating).unwrap(), b"AxyDE");
+    }
+
+    #[test]
+    fn rejects_truncated_ips() {
+        let patch = b"PATCH\x00\x00\x01\x00\x02xy\x00\x00\x06\x00\x00\x00\x04zEOF";
+        for length in 5..patch.len() {
+            assert_eq!(message(apply(SOURCE.to_vec(), &patch[..length])), "truncated patch");
+        }
+    }
+
+    #[test]
+    fn applies_ups() {
+        assert_eq!(apply(SOURCE.to_vec(), &ups(SOURCE, b"AbcDEFGh")).unwrap(), b"AbcDEFGh");
+        assert_eq!(apply(SOURCE.to_vec(), &ups(SOURCE, b"ABCDEFGHIJ")).unwrap(), b"ABCDEFGHIJ");
+        assert_eq!(apply(SOURCE.to_vec(), &ups(SOURCE, b"ABC")).unwrap(), b"ABC");
+    }
+
+    #[test]
+    fn rejects_bad_ups() {
+        let patch = ups(SOURCE, b"AbcDEFGh");
+        assert_eq!(message(apply(b"ABCDEFGX".to_vec(), &patch)), "the patch is for a different rom");
+
+        let mut damaged = patch.clone();
+        damaged[8] ^= 1;
+        assert_eq!(message(apply(SOURCE.to_vec(), &damaged)), "the patch is damaged, its crc doesn't match");
+
+        for length in 4..patch.len() {
+            assert!(apply(SOURCE.to_vec(), &patch[..length]).is_err());
+        }
+
+        // A hunk that runs into the footer
+        let mut body = patch[..patch.len() - 13].to_vec();
+        body.push(b'x');
+        assert_eq!(message(apply(SOURCE.to_vec(), &finish(body, SOURCE, crc32(b"AbcDEFGh")))), "truncated patch");
+
+        let mut huge = b"UPS1".to_vec();
+        huge.extend(varint(SOURCE.len()));
+        huge.extend(varint(1 << 40));
+        assert_eq!(message(apply(SOURCE.to_vec(), &finish(huge, SOURCE, 0))), "the patched rom would be too big");
+    }
+
+    #[test]
+    fn applies_bps() {
+        assert_eq!(apply(SOURCE.to_vec(), &bps()).unwrap(), BPS_TARGET);
+    }
+
+    #[test]
+    fn rejects_bad_bps() {
+        let patch = bps();
+        assert_eq!(message(apply(b"ABCDEFGX".to_vec(), &patch)), "the patch is for a different rom");
+
+        let mut damaged = patch.clone();
+        damaged[12] ^= 1;
+        assert_eq!(message(apply(SOURCE.to_vec(), &damaged)), "the patch is damaged, its crc doesn't match");
+
+        for length in 4..patch.len() {
+            assert!(apply(SOURCE.to_vec(), &patch[..length]).is_err());
+        }
+
+        let wrong_target = finish(bps_body(BPS_TARGET.len()), SOURCE, crc32(b"something else"));
+        assert_eq!(message(apply(SOURCE.to_vec(), &wrong_target)), "the patched rom's crc doesn't match");
+
+        let too_short = finish(bps_body(BPS_TARGET.len() - 1), SOURCE, crc32(BPS_TARGET));
+        assert_eq!(message(apply(SOURCE.to_vec(), &too_short)), "patch writes past the end of the rom");
+
+        let too_long = finish(bps_body(BPS_TARGET.len() + 1), SOURCE, crc32(BPS_TARGET));
+        assert_eq!(message(apply(SOURCE.to_vec(), &too_long)), "the patched rom is the wrong size");
+
+        assert_eq!(message(apply(SOURCE.to_vec(), &finish(bps_body(1 << 40), SOURCE, 0))), "the patched rom would be too big");
+
+        // Copies from before the start or past the end of the source
+        for offset in [1 << 1 | 1, 7 << 1] {
+            let mut body = b"BPS1".to_vec();
+            body.extend(varint(SOURCE.len()));
+            body.extend(varint(2));
+            body.extend(varint(0));
+            body.extend(varint((2 - 1) << 2 | 2));
+            body.extend(varint(offset));
+            assert_eq!(message(apply(SOURCE.to_vec(), &finish(body, SOURCE, 0))), "bad copy in patch");
+        }
+    }
+}
@@ -3,9 +3,12 @@
 use crate::memory::Memory;
 use crate::registers::*;
 use crate::cpu::CPU;
+use crate::savestate::{StateReader, StateWriter};
+use crate::palette::Palette;
 
 use sdl2::{
-    pixels::PixelFormatEnum,
+    gfx::primitives::DrawRenderer,
+    pixels::{Color, PixelFormatEnum},
     render::{
         Canvas,
         Texture
@@ -13,9 +16,12 @@ use sdl2::{
     video::Window,
     event::Event,
     EventPump,
+    Sdl,
+    VideoSubsystem,
 };
 
 use std::borrow::BorrowMut;
+use std::collections::VecDeque;
 use std::{thread, time};
 
 //////////////////////////////// MACROS ////////////////////////////////
@@ -35,21 +41,24 @@ pub struct SDLRenderer {
     height: u32,
     canvas: Canvas<Window>,
     texture: Texture,
-    pub displaybuffer: Vec<u8>,
     pub event_pump: EventPump,
+    pub sdl_context: Sdl,
+    pub video_subsystem: VideoSubsystem,
+    notifications: VecDeque<(String, u32)>, // text and frames left on screen, shown one at a time
 }
 
 impl SDLRenderer {
     const PIXELSIZE:usize = 4;
+    const NOTIFICATION_FRAMES:u32 = 180;
 
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32, scale: u32) -> Self {
         let sdl_context = sdl2::init().expect("failed to create sdl context");
 
         let event_pump = sdl_context.event_pump().expect("failed to create event pump");
 
         let video_subsystem = sdl_context.video().expect("failed to get video context");
 
-        let window = video_subsystem.window("Nemulator", width * 3, height * 3)
+        let window = video_subsystem.window("Nemulator", width * scale, height * scale)
         .build()
         .expect("failed to build window");
     
@@ -70,27 +79,41 @@ impl SDLRenderer {
             height,
             canvas,
             texture,
-            displaybuffer: vec![0; 160 * 144 * Self::PIXELSIZE],
             event_pump,
+            sdl_context,
+            video_subsystem,
+            notifications: VecDeque::new(),
         }
     }
 
-    fn update(&mut self) {
+    // Shows a line of text along the bottom of the window for a few seconds
+    pub fn notify(&mut self, text: &str) {
+        self.notifications.push_back((text.to_string(), Self::NOTIFICATION_FRAMES));
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn update(&mut self, displaybuffer: &[u8]) {
         self.texture
-            .update(None, &self.displaybuffer, self.width as usize * Self::PIXELSIZE);
+            .update(None, displaybuffer, self.width as usize * Self::PIXELSIZE);
         self.canvas
             .copy(&self.texture, None, None);
+        if let Some((text, frames_left)) = self.notifications.front_mut() {
+            let (width, height) = self.canvas.output_size().unwrap_or((self.width, self.height));
+            let (width, height) = (width as i16, height as i16);
+            self.canvas.box_(0, height - 16, width - 1, height - 1, Color::RGBA(0, 0, 0, 200)).ok();
+            self.canvas.string(4, height - 12, text, Color::RGB(255, 255, 255)).ok();
+            *frames_left -= 1;
+            if *frames_left == 0 {
+                self.notifications.pop_front();
+            }
+        }
         self.canvas.present();
     }
 }
 
-pub enum Palette {
-    Grayscale,
-    Redscale,
-    Bluescale,
-    Greenscale,
-}
-
 /////////////////////////////// SPRITE ///////////////////////////////
 
 pub struct Sprite {
@@ -132,13 +155,15 @@ impl SpritePixel {
 pub struct BackgroundPixel {
     colour_id: u8,
     palette: u16,
+    window: bool, // fetched from the window rather than the background
 }
 
 impl BackgroundPixel {
-    pub fn new(colour_id: u8, palette: u16) -> Self {
+    pub fn new(colour_id: u8, palette: u16, window: bool) -> Self {
         BackgroundPixel {
             colour_id,
             palette,
+            window,
         }
     }
 }
@@ -258,6 +283,26 @@ pub enum FetcherState {
     PushToFifo,
 }
 
+impl FetcherState {
+    fn to_u8(&self) -> u8 {
+        match self {
+            FetcherState::TileNumber => 0,
+            FetcherState::TileDataLow => 1,
+            FetcherState::TileDataHigh => 2,
+            FetcherState::PushToFifo => 3,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => FetcherState::TileNumber,
+            1 => FetcherState::TileDataLow,
+            2 => FetcherState::TileDataHigh,
+            _ => FetcherState::PushToFifo,
+        }
+    }
+}
+
 pub struct PixelFetcher {
     fetcher_x: u8,
     window_line_counter: u8,
@@ -367,7 +412,7 @@ impl PixelFetcher { // pixel fetcher fetches 1 row of a tile at a time
                 let colour_low = ((self.tile_data_low & (0b10000000 >> pixel_number)) >> (7 - pixel_number));
                 let colour = colour_high | colour_low;
                 
-                let pixel = BackgroundPixel::new(colour, 0xFF47);
+                let pixel = BackgroundPixel::new(colour, 0xFF47, self.rendering_window);
 
                 self.bgwin_fifo.add(pixel);
                 //println!("BGWIN FIFO LEN => {}", self.bgwin_fifo.len);
@@ -452,6 +497,39 @@ impl PixelFetcher { // pixel fetcher fetches 1 row of a tile at a time
             // println!("SPRITE FIFO LEN => {}", self.sprite_fifo.len);
         }
     }
+
+    // The fifos aren't saved - states are taken between frames, and both are cleared before mode 3
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.fetcher_x);
+        state.u8(self.window_line_counter);
+        state.u8(self.tile_number);
+        state.u8(self.tile_data_low);
+        state.u8(self.tile_data_high);
+        state.u8(self.sprite_tile_data_low);
+        state.u8(self.sprite_tile_data_high);
+        state.bool(self.rendering_window);
+        state.u8(self.cycles);
+        state.u8(self.bgwin_state.to_u8());
+        state.u8(self.sprite_state.to_u8());
+        state.bool(self.first_tile);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) {
+        self.fetcher_x = state.u8();
+        self.window_line_counter = state.u8();
+        self.tile_number = state.u8();
+        self.tile_data_low = state.u8();
+        self.tile_data_high = state.u8();
+        self.sprite_tile_data_low = state.u8();
+        self.sprite_tile_data_high = state.u8();
+        self.rendering_window = state.bool();
+        self.cycles = state.u8();
+        self.bgwin_state = FetcherState::from_u8(state.u8());
+        self.sprite_state = FetcherState::from_u8(state.u8());
+        self.first_tile = state.bool();
+        self.sprite_fifo.clear();
+        self.bgwin_fifo.clear();
+    }
 }
 
 /////////////////////////////// PPU ////////////////////////////////
@@ -468,6 +546,7 @@ pub struct PPU {
     pub rendering_window: bool,
     pub entered_window: bool,
     pub entered_vblank: bool,
+    pub frame_ready: bool, // set once a frame has been presented, cleared by whoever consumes it
     pub stat_irq: bool,
     pub first_irq_on_scanline: bool,
 
@@ -477,16 +556,23 @@ pub struct PPU {
     pub fetching_sprite: bool,
     pub sprite_to_render: Sprite,
 
-    pub renderer: SDLRenderer,
+    pub renderer: Option<SDLRenderer>, // None when running headless
+    pub displaybuffer: Vec<u8>, // 160x144 pixels in RGB888 byte order (B, G, R, unused)
     pub displaybuffer_index: usize,
     pub pixel_fetcher: PixelFetcher,
     pub selected_palette: Palette,
 
+    // Debug layer toggles, applied on top of LCDC
+    pub show_background: bool,
+    pub show_window: bool,
+    pub show_sprites: bool,
+    pub sprite_limit: bool, // 10 sprites per line, as on hardware
+
     pub time_step: time::SystemTime,
 }
 
 impl PPU  {
-    pub fn new(selected_palette: Palette) -> Self {
+    pub fn new(selected_palette: Palette, renderer: Option<SDLRenderer>) -> Self {
         PPU {
             enabled: true,
             mode: 2,
@@ -499,6 +585,7 @@ impl PPU  {
             rendering_window: false,
             entered_window: false,
             entered_vblank: false,
+            frame_ready: false,
             stat_irq: false,
             first_irq_on_scanline: false,
 
@@ -508,15 +595,26 @@ impl PPU  {
             fetching_sprite: false,
             sprite_to_render: Sprite::new(0, 0, 0, 0),
 
-            renderer: SDLRenderer::new(160, 144),
+            renderer,
+            displaybuffer: vec![0; 160 * 144 * 4],
             displaybuffer_index: 0,
             pixel_fetcher: PixelFetcher::new(),
             selected_palette,
+
+            show_background: true,
+            show_window: true,
+            show_sprites: true,
+            sprite_limit: true,
             
             time_step: time::SystemTime::now(),
         }
     }
 
+    // The window, for code that only runs with one (the main loop, debug viewers...)
+    pub fn renderer(&mut self) -> &mut SDLRenderer {
+        self.renderer.as_mut().expect("no window when running headless")
+    }
+
     pub fn tick(&mut self, memory: &mut Memory) {
         self.step(memory);
         self.step(memory);
@@ -572,7 +670,8 @@ impl PPU  {
         let wx = memory.read(0xFF4B).wrapping_sub(8);
         let window_enabled = if memory.read(0xFF40) & 0b00100000 == 0 { false } else { true };
         // println!("LCDC => {:b}", memory.read(0xFF40));
-        if self.ly >= wy && self.x >= wx && window_enabled {
+        // A hidden window is never entered, so the background behind it keeps being fetched
+        if self.ly >= wy && self.x >= wx && window_enabled && self.show_window {
             self.rendering_window = true;
             self.pixel_fetcher.rendering_window = true;
             // println!("RENDERING WINDOW");
@@ -624,14 +723,14 @@ impl PPU  {
             memory.write(0xFF44, 0);
             self.cycles = 0;
             self.x = 0;
-            self.renderer.update();
+            if let Some(renderer) = self.renderer.as_mut() {
+                renderer.update(&self.displaybuffer);
+            }
+            self.frame_ready = true;
             self.displaybuffer_index = 0;
             self.entered_vblank = false;
             self.pixel_fetcher.window_line_counter = 0;
-
-            /*let time_elapsed = self.time_step.elapsed().unwrap().as_millis() as u64;
-            thread::sleep(time::Duration::from_millis(17 - time_elapsed));
-            self.time_step = time::SystemTime::now();*/
+            // Frame pacing is done by the Pacer once the frame is picked up, see frame_ready
         }
         if self.cycles == 456 {
             self.inc_ly(memory);
@@ -644,7 +743,7 @@ impl PPU  {
             memory.write(0xFF44, 0);
             let stat = memory.read(0xFF41);
             memory.write(0xFF41, stat & !0b0000_0011);
-            self.renderer.displaybuffer.fill(0);
+            self.displaybuffer.fill(0);
         }
     }
 
@@ -789,7 +888,7 @@ impl PPU  {
             return
         }; 
 
-        if self.sprite_buffer.len() == 10 {
+        if self.sprite_buffer.len() == 10 && self.sprite_limit {
             return
         };
 
@@ -818,78 +917,114 @@ impl PPU  {
 
 ////////////////////////////////////////////////////////////////////
 
+    fn layer_visible(&self, pixel: &BackgroundPixel) -> bool {
+        if pixel.window { self.show_window } else { self.show_background }
+    }
+
     pub fn push_to_lcd(&mut self, memory: &mut Memory) {
         let lcdc = memory.read(0xFF40);
-        let colour = if !self.pixel_fetcher.sprite_fifo.is_empty() && !self.pixel_fetcher.bgwin_fifo.is_empty() { // mix
+        let rgb = if !self.pixel_fetcher.sprite_fifo.is_empty() && !self.pixel_fetcher.bgwin_fifo.is_empty() { // mix
             // println!("SPRITE FIFO HAS DATA @ ({}, {})", self.x, self.ly);
             let mut bg_pixel = self.pixel_fetcher.bgwin_fifo.remove().unwrap();
             let mut sprite_pixel = self.pixel_fetcher.sprite_fifo.remove().unwrap();
-            if lcdc & 0b0000_0001 == 0 { bg_pixel.colour_id = 0 };
-            if lcdc & 0b0000_0010 == 0 { sprite_pixel.colour_id = 0 };
+            if lcdc & 0b0000_0001 == 0 || !self.layer_visible(&bg_pixel) { bg_pixel.colour_id = 0 };
+            if lcdc & 0b0000_0010 == 0 || !self.show_sprites { sprite_pixel.colour_id = 0 };
             // println!("LCDC => {:#010b} @ ({}, {})", lcdc, self.x, self.ly);
             // println!("COLOUR => {} | PALETTE => {} | PRIORITY => {}", sprite_pixel.colour_id, sprite_pixel.palette, sprite_pixel.priority);
 
             if sprite_pixel.colour_id == 0 || (sprite_pixel.priority == 1 && bg_pixel.colour_id != 0) {
                 let palette = memory.read(bg_pixel.palette); // aka which 2 bits of the palette to use
                 let colour = (palette & (0b00000011 << (bg_pixel.colour_id * 2))) >> (bg_pixel.colour_id * 2);
-                colour
+                self.selected_palette.rgb(colour)
             } else {
                 // println!("RENDERING SPRITE PIXEL @ ({},{})", self.x, self.ly);
                 let palette = memory.read(sprite_pixel.palette); // aka which 2 bits of the palette to use
                 let colour = (palette & (0b00000011 << (sprite_pixel.colour_id * 2))) >> (sprite_pixel.colour_id * 2);
-                colour
+                self.selected_palette.obj_rgb(sprite_pixel.palette == 0xFF49, colour)
             }
         } else { // only bother with bg
             let mut bg_pixel = self.pixel_fetcher.bgwin_fifo.remove().unwrap(); // pixel.colour tells us the id 
-            bg_pixel.colour_id = if lcdc & 0b0000_0001 == 0 { 0 } else { bg_pixel.colour_id };
+            bg_pixel.colour_id = if lcdc & 0b0000_0001 == 0 || !self.layer_visible(&bg_pixel) { 0 } else { bg_pixel.colour_id };
             let palette = memory.read(bg_pixel.palette); // aka which 2 bits of the palette to use
             let colour = (palette & (0b00000011 << (bg_pixel.colour_id * 2))) >> (bg_pixel.colour_id * 2);
-            colour
+            self.selected_palette.rgb(colour)
         };
 
-        let rgb = match colour {
-            0 => { 
-                match &self.selected_palette {
-                    Palette::Grayscale => vec![255, 255, 255],
-                    Palette::Bluescale => vec![188, 15, 15],
-                    Palette::Greenscale => vec![155, 188, 15],
-                    Palette::Redscale => vec![15, 15, 188],
-                }
-            },
-            1 => { 
-                match &self.selected_palette {
-                    Palette::Grayscale => vec![169, 169, 169],
-                    Palette::Bluescale => vec![172, 15, 15],
-                    Palette::Greenscale => vec![139, 172, 15],
-                    Palette::Redscale => vec![15, 15, 172],
-                }
-            }
-            2 => { 
-                match &self.selected_palette {
-                    Palette::Grayscale => vec![84, 84, 84],
-                    Palette::Bluescale => vec![98, 48, 48],
-                    Palette::Greenscale => vec![48, 98, 48],
-                    Palette::Redscale => vec![48, 48, 98],
-                }
-            }
-            3 => { 
-                match &self.selected_palette {
-                    Palette::Grayscale => vec![0, 0, 0],
-                    Palette::Bluescale => vec![56, 15, 15],
-                    Palette::Greenscale => vec![15, 56, 15],
-                    Palette::Redscale => vec![15, 15, 56],
-                }
-            }
-            _ => unreachable!()
-        };
 
-        self.renderer.displaybuffer[self.displaybuffer_index] = rgb[0];
+        self.displaybuffer[self.displaybuffer_index] = rgb[0];
         self.displaybuffer_index = self.displaybuffer_index.wrapping_add(1);
-        self.renderer.displaybuffer[self.displaybuffer_index] = rgb[1];
+        self.displaybuffer[self.displaybuffer_index] = rgb[1];
         self.displaybuffer_index = self.displaybuffer_index.wrapping_add(1);
-        self.renderer.displaybuffer[self.displaybuffer_index] = rgb[2];
+        self.displaybuffer[self.displaybuffer_index] = rgb[2];
         self.displaybuffer_index = self.displaybuffer_index.wrapping_add(2);
 
         self.rendering_window(memory);
     }
-}
\ No newline at end of file
+
+    // Includes the displaybuffer, so a loaded state can be shown straight away without emulating a frame.
+    // The debug layer toggles, palette and renderer belong to the host rather than the game so they're left alone.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.u8(self.mode);
+        state.u16(self.cycles);
+        state.u8(self.ly);
+        state.u8(self.x);
+
+        state.u16(self.mode_3_penalty);
+        state.u16(self.obj_penalty);
+        state.bool(self.rendering_window);
+        state.bool(self.entered_window);
+        state.bool(self.entered_vblank);
+        state.bool(self.stat_irq);
+        state.bool(self.first_irq_on_scanline);
+
+        state.u16(self.oam_pointer as u16);
+        state.u8(self.sprite_buffer.len() as u8);
+        for sprite in self.sprite_buffer.iter() {
+            state.bytes(&[sprite.y, sprite.x, sprite.index, sprite.attributes]);
+        }
+        state.u8(self.obj_checked_tiles.len() as u8);
+        state.bytes(&self.obj_checked_tiles);
+        state.bool(self.fetching_sprite);
+        let sprite = &self.sprite_to_render;
+        state.bytes(&[sprite.y, sprite.x, sprite.index, sprite.attributes]);
+
+        state.u64(self.displaybuffer_index as u64);
+        self.pixel_fetcher.save_state(state);
+        state.bytes(&self.displaybuffer);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) {
+        self.enabled = state.bool();
+        self.mode = state.u8();
+        self.cycles = state.u16();
+        self.ly = state.u8();
+        self.x = state.u8();
+
+        self.mode_3_penalty = state.u16();
+        self.obj_penalty = state.u16();
+        self.rendering_window = state.bool();
+        self.entered_window = state.bool();
+        self.entered_vblank = state.bool();
+        self.stat_irq = state.bool();
+        self.first_irq_on_scanline = state.bool();
+
+        self.oam_pointer = state.u16() as usize;
+        let mut sprite = [0_u8; 4];
+        self.sprite_buffer.clear();
+        for _ in 0..state.u8() {
+            state.bytes(&mut sprite);
+            self.sprite_buffer.push(Sprite::new(sprite[0], sprite[1], sprite[2], sprite[3]));
+        }
+        self.obj_checked_tiles = vec![0; state.u8() as usize];
+        state.bytes(&mut self.obj_checked_tiles);
+        self.fetching_sprite = state.bool();
+        state.bytes(&mut sprite);
+        self.sprite_to_render = Sprite::new(sprite[0], sprite[1], sprite[2], sprite[3]);
+
+        self.displaybuffer_index = state.u64() as usize;
+        self.pixel_fetcher.load_state(state);
+        state.bytes(&mut self.displaybuffer);
+        self.frame_ready = false;
+    }
+}
@@ -0,0 +1,93 @@
+use crate::cpu::CPU;
+use crate::savestate;
+
+use std::collections::VecDeque;
+
+// Rewind buffer. Every `interval` frames a save state is taken. Only the newest state is kept whole,
+// older ones are kept as the compressed XOR of each state with the one after it, so stepping back is
+// newest ^ delta. When the deltas outgrow the byte budget the oldest are dropped.
+
+pub struct Rewind {
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>, // oldest at the front
+    used_bytes: usize,
+    frames_since_snapshot: u32,
+
+    pub capacity_bytes: usize,
+    pub interval: u32, // frames between snapshots
+}
+
+impl Rewind {
+    pub fn new(capacity_bytes: usize, interval: u32) -> Self {
+        Rewind {
+            newest: None,
+            deltas: VecDeque::new(),
+            used_bytes: 0,
+            frames_since_snapshot: 0,
+
+            capacity_bytes,
+            interval: interval.max(1),
+        }
+    }
+
+    // Called once per emulated frame
+    pub fn record(&mut self, cpu: &CPU) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        let state = cpu.save_state();
+        if let Some(newest) = self.newest.take() {
+            if newest.len() == state.len() {
+                let delta = savestate::compress(&savestate::xor(&newest, &state));
+                self.used_bytes += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                self.clear(); // layout changed under us (shouldn't happen), the old deltas are useless
+            }
+        }
+        self.newest = Some(state);
+
+        while self.used_bytes + self.newest_len() > self.capacity_bytes {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Loads the snapshot before the newest one. Returns false once the buffer is exhausted,
+    // in which case the cpu is left at the oldest state still held.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        self.frames_since_snapshot = 0;
+        let newest = match self.newest.as_ref() {
+            Some(newest) => newest,
+            None => return false,
+        };
+        let previous = match self.deltas.pop_back() {
+            Some(delta) => {
+                self.used_bytes -= delta.len();
+                savestate::xor(newest, &savestate::decompress(&delta, newest.len()))
+            },
+            None => {
+                cpu.load_state(newest);
+                return false;
+            },
+        };
+        cpu.load_state(&previous);
+        self.newest = Some(previous);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.used_bytes = 0;
+    }
+
+    fn newest_len(&self) -> usize {
+        self.newest.as_ref().map(|state| state.len()).unwrap_or(0)
+    }
+}
@@ -0,0 +1,159 @@
+// Save state serialisation helpers. Each component writes its own fields in a fixed order
+// (see save_state / load_state on CPU, Memory, Timer and PPU), so a state is only valid
+// for the same build that wrote it - the header's version is bumped whenever a layout changes.
+
+pub const MAGIC: &[u8; 4] = b"NEMU";
+pub const VERSION: u8 = 2;
+
+pub struct StateWriter {
+    pub data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        StateWriter { data }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+}
+
+// States can come from files (movies), so reading past the end doesn't panic: it reads zeroes and
+// marks the state as short. Check complete() once everything is read and throw the result away if not.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    short: bool,
+}
+
+impl<'a> StateReader<'a> {
+    // Checks the header, returning None if this isn't a state written by this version
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 5 || &data[0..4] != MAGIC || data[4] != VERSION {
+            return None;
+        }
+        Some(StateReader { data, position: 5, short: false })
+    }
+
+    // True if every read was in bounds and the whole state was read
+    pub fn complete(&self) -> bool {
+        !self.short && self.position == self.data.len()
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        let mut byte = [0_u8; 1];
+        self.bytes(&mut byte);
+        byte[0]
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.u8() != 0
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        let mut bytes = [0_u8; 2];
+        self.bytes(&mut bytes);
+        u16::from_le_bytes(bytes)
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        let mut bytes = [0_u8; 8];
+        self.bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn bytes(&mut self, destination: &mut [u8]) {
+        let len = destination.len();
+        match self.data.get(self.position..self.position + len) {
+            Some(source) => destination.copy_from_slice(source),
+            None => { destination.fill(0); self.short = true; },
+        }
+        self.position += len;
+    }
+}
+
+/////////////////////////////// COMPRESSION ////////////////////////////////
+
+// Consecutive snapshots are mostly identical, so deltas are stored as the XOR of the two states.
+// XOR deltas are symmetric: previous = current ^ delta, and current = previous ^ delta.
+pub fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b.iter()).map(|(a, b)| a ^ b).collect()
+}
+
+// A delta is mostly zeroes, so it is run length encoded as pairs of
+// (zero run length, literal length, literal bytes...) with lengths as LEB128 varints
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeroes_start = i;
+        while i < data.len() && data[i] == 0 { i += 1; }
+        let literal_start = i;
+        // a literal ends at the next run of 4+ zeroes, shorter runs are cheaper to store inline
+        while i < data.len() && !(i + 4 <= data.len() && data[i..i + 4] == [0, 0, 0, 0]) { i += 1; }
+        write_varint(&mut output, literal_start - zeroes_start);
+        write_varint(&mut output, i - literal_start);
+        output.extend_from_slice(&data[literal_start..i]);
+    }
+    output
+}
+
+pub fn decompress(data: &[u8], len: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < data.len() {
+        let zeroes = read_varint(data, &mut i);
+        let literal = read_varint(data, &mut i);
+        output.resize(output.len() + zeroes, 0);
+        output.extend_from_slice(&data[i..i + literal]);
+        i += literal;
+    }
+    output.resize(len, 0);
+    output
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,349 @@
+use crate::memory::Memory;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Where each game keeps its score, so high scores can be tracked for new games by editing a file.
+// Definitions are keyed by the header title or by the header's global checksum (0x014E-0x014F, in hex):
+//
+//   [title:TETRIS]
+//   bytes = C0A0-C0A2            # one address or an inclusive range
+//   encoding = bcd_le            # bcd_le, bcd_be, le, be or digits
+//   valid = C0A3 == 00           # optional, every valid line has to hold: ADDR[&MASK] OP VALUE, all hex
+//
+// bcd_* packs two decimal digits per byte, le / be are plain binary, both little or big-endian.
+// digits is one byte per decimal digit in reading order, where digit_base (hex, default 00) is the byte
+// for 0 and anything outside 0-9 counts as a blank. A read that isn't valid is ignored for that frame.
+
+pub const DEFAULT_DEFINITIONS: &str = "\
+# Tetris and Dr. Mario keep their score as 3 BCD bytes, least significant first
+[title:TETRIS]
+bytes = C0A0-C0A2
+encoding = bcd_le
+
+[title:DR.MARIO]
+bytes = C0A0-C0A2
+encoding = bcd_le
+";
+
+#[derive(Clone, PartialEq)]
+pub enum Key {
+    Title(String),
+    Checksum(u16),
+}
+
+#[derive(Copy, Clone)]
+pub enum Encoding {
+    BcdLittle,
+    BcdBig,
+    Little,
+    Big,
+    Digits(u8), // the byte for 0
+}
+
+#[derive(Copy, Clone)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Copy, Clone)]
+pub struct Condition {
+    pub address: u16,
+    pub mask: u8,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Self> {
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let (address, mask) = match parts[0].split_once('&') {
+            Some((address, mask)) => (address, u8::from_str_radix(mask, 16).ok()?),
+            None => (parts[0], 0xFF),
+        };
+        let comparison = match parts[1] {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterEqual,
+            _ => return None,
+        };
+        Some(Condition {
+            address: u16::from_str_radix(address, 16).ok()?,
+            mask,
+            comparison,
+            value: u8::from_str_radix(parts[2], 16).ok()?,
+        })
+    }
+
+    pub fn holds(&self, memory: &Memory) -> bool {
+        let byte = memory.read(self.address) & self.mask;
+        match self.comparison {
+            Comparison::Equal => byte == self.value,
+            Comparison::NotEqual => byte != self.value,
+            Comparison::Less => byte < self.value,
+            Comparison::LessEqual => byte <= self.value,
+            Comparison::Greater => byte > self.value,
+            Comparison::GreaterEqual => byte >= self.value,
+        }
+    }
+}
+
+pub struct ScoreDefinition {
+    pub key: Key,
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub encoding: Encoding,
+    pub conditions: Vec<Condition>,
+}
+
+impl ScoreDefinition {
+    // The score currently in memory, None if it isn't valid right now
+    pub fn read(&self, memory: &Memory) -> Option<i32> {
+        if !self.conditions.iter().all(|condition| condition.holds(memory)) {
+            return None;
+        }
+        let mut bytes: Vec<u8> = (self.start..=self.end).map(|address| memory.read(address)).collect();
+        if let Encoding::BcdLittle | Encoding::Little = self.encoding {
+            bytes.reverse(); // most significant first from here on
+        }
+
+        let mut score: i64 = 0;
+        for byte in bytes {
+            score = match self.encoding {
+                Encoding::BcdLittle | Encoding::BcdBig => {
+                    let (high, low) = (byte >> 4, byte & 0x0F);
+                    if high > 9 || low > 9 {
+                        return None;
+                    }
+                    score * 100 + (high * 10 + low) as i64
+                },
+                Encoding::Little | Encoding::Big => (score << 8) | byte as i64,
+                Encoding::Digits(base) => match byte.wrapping_sub(base) {
+                    digit @ 0..=9 => score * 10 + digit as i64,
+                    _ => score * 10,
+                },
+            };
+            if score > i32::MAX as i64 {
+                return None;
+            }
+        }
+        Some(score as i32)
+    }
+}
+
+pub struct ScoreDefinitions {
+    pub definitions: Vec<ScoreDefinition>,
+}
+
+impl ScoreDefinitions {
+    pub fn parse(text: &str, source: &str) -> Self {
+        let mut definitions: Vec<ScoreDefinition> = Vec::new();
+        let complete = |definition: Option<ScoreDefinition>, definitions: &mut Vec<ScoreDefinition>| {
+            if let Some(definition) = definition {
+                if definition.end < definition.start {
+                    println!("IGNORING SCORE DEFINITION WITHOUT bytes IN {}", source);
+                } else {
+                    definitions.push(definition);
+                }
+            }
+        };
+
+        let mut current: Option<ScoreDefinition> = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(key) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                complete(current.take(), &mut definitions);
+                let key = match key.split_once(':') {
+                    Some(("title", title)) => Some(Key::Title(title.to_string())),
+                    Some(("checksum", checksum)) => u16::from_str_radix(checksum, 16).ok().map(Key::Checksum),
+                    _ => None,
+                };
+                match key {
+                    // start > end until bytes is given
+                    Some(key) => current = Some(ScoreDefinition { key, start: 1, end: 0, encoding: Encoding::BcdBig, conditions: Vec::new() }),
+                    None => println!("IGNORING INVALID SCORE DEFINITION ON LINE {} OF {}: {}", number + 1, source, line),
+                }
+                continue;
+            }
+
+            let definition = match current.as_mut() {
+                Some(definition) => definition,
+                None => continue, // the rest of an ignored definition
+            };
+            let parsed = line.split_once('=').and_then(|(field, value)| {
+                let value = value.trim();
+                match field.trim() {
+                    "bytes" => {
+                        let (start, end) = value.split_once('-').unwrap_or((value, value));
+                        let range = (u16::from_str_radix(start.trim(), 16).ok()?, u16::from_str_radix(end.trim(), 16).ok()?);
+                        (definition.start, definition.end) = range;
+                    },
+                    "encoding" => definition.encoding = match value {
+                        "bcd_le" => Encoding::BcdLittle,
+                        "bcd_be" => Encoding::BcdBig,
+                        "le" => Encoding::Little,
+                        "be" => Encoding::Big,
+                        "digits" => Encoding::Digits(match definition.encoding { Encoding::Digits(base) => base, _ => 0 }),
+                        _ => return None,
+                    },
+                    "digit_base" => definition.encoding = Encoding::Digits(u8::from_str_radix(value, 16).ok()?),
+                    "valid" => definition.conditions.push(Condition::parse(value)?),
+                    _ => return None,
+                }
+                Some(())
+            });
+            if parsed.is_none() {
+                println!("IGNORING INVALID SCORE DEFINITION ON LINE {} OF {}: {}", number + 1, source, line);
+            }
+        }
+        complete(current, &mut definitions);
+        ScoreDefinitions { definitions }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(ScoreDefinitions::parse(&fs::read_to_string(path)?, &path.display().to_string()))
+    }
+
+    // Writes the defaults out when the file doesn't exist yet, so there's something to extend
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            if let Err(e) = fs::write(path, DEFAULT_DEFINITIONS) {
+                println!("UNABLE TO WRITE {}: {}", path.display(), e);
+            }
+            return ScoreDefinitions::parse(DEFAULT_DEFINITIONS, "the default score definitions");
+        }
+        match ScoreDefinitions::load(path) {
+            Ok(definitions) => definitions,
+            Err(e) => {
+                println!("UNABLE TO READ {}: {}", path.display(), e);
+                ScoreDefinitions::parse(DEFAULT_DEFINITIONS, "the default score definitions")
+            },
+        }
+    }
+
+    // A checksum match wins over a title match, for telling apart revisions that share a title
+    pub fn find(&self, title: &str, checksum: u16) -> Option<&ScoreDefinition> {
+        self.definitions.iter().find(|definition| definition.key == Key::Checksum(checksum))
+            .or_else(|| self.definitions.iter().find(|definition| definition.key == Key::Title(title.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_with(address: u16, bytes: &[u8]) -> Memory {
+        let mut memory = Memory::new();
+        for (offset, &byte) in bytes.iter().enumerate() {
+            memory.write(address + offset as u16, byte);
+        }
+        memory
+    }
+
+    fn single(text: &str) -> ScoreDefinition {
+        let mut definitions = ScoreDefinitions::parse(text, "test").definitions;
+        assert_eq!(definitions.len(), 1);
+        definitions.remove(0)
+    }
+
+    #[test]
+    fn parses_the_defaults() {
+        let definitions = ScoreDefinitions::parse(DEFAULT_DEFINITIONS, "test");
+        assert_eq!(definitions.definitions.len(), 2);
+        let tetris = definitions.find("TETRIS", 0).unwrap();
+        assert!(tetris.start == 0xC0A0 && tetris.end == 0xC0A2);
+        assert!(matches!(tetris.encoding, Encoding::BcdLittle));
+        assert!(definitions.find("ZELDA", 0).is_none());
+    }
+
+    #[test]
+    fn checksum_wins_over_title() {
+        let definitions = ScoreDefinitions::parse("[title:GAME]\nbytes = C000\n[checksum:1a2B]\nbytes = C001\n", "test");
+        assert_eq!(definitions.find("GAME", 0x1A2B).unwrap().start, 0xC001);
+        assert_eq!(definitions.find("GAME", 0x1A2C).unwrap().start, 0xC000);
+    }
+
+    #[test]
+    fn skips_broken_definitions() {
+        let text = "\
+[title:NO BYTES]
+encoding = le
+[unknown:X]
+bytes = C000
+[checksum:XYZ]
+bytes = C000
+[title:TRUNCATED
+bytes = C000
+[title:GOOD]   # comment
+bytes = C010-C011
+encoding = nonsense
+valid = C012 ~ 01
+colour = blue
+bytes = C0-
+";
+        let definition = single(text);
+        assert!(definition.key == Key::Title(String::from("GOOD")));
+        assert!(definition.start == 0xC010 && definition.end == 0xC011);
+        assert!(matches!(definition.encoding, Encoding::BcdBig));
+        assert!(definition.conditions.is_empty());
+    }
+
+    #[test]
+    fn backwards_range_is_dropped() {
+        assert!(ScoreDefinitions::parse("[title:X]\nbytes = C002-C000\n", "test").definitions.is_empty());
+        assert!(ScoreDefinitions::parse("", "test").definitions.is_empty());
+    }
+
+    #[test]
+    fn reads_bcd() {
+        let memory = memory_with(0xC0A0, &[0x56, 0x34, 0x12]);
+        assert_eq!(single("[title:X]\nbytes = C0A0-C0A2\nencoding = bcd_le").read(&memory), Some(123456));
+        assert_eq!(single("[title:X]\nbytes = C0A0-C0A2\nencoding = bcd_be").read(&memory), Some(563412));
+        let memory = memory_with(0xC0A0, &[0x5A, 0x34]);
+        assert_eq!(single("[title:X]\nbytes = C0A0-C0A1\nencoding = bcd_le").read(&memory), None);
+    }
+
+    #[test]
+    fn reads_binary() {
+        let memory = memory_with(0xC000, &[0x34, 0x12]);
+        assert_eq!(single("[title:X]\nbytes = C000-C001\nencoding = le").read(&memory), Some(0x1234));
+        assert_eq!(single("[title:X]\nbytes = C000-C001\nencoding = be").read(&memory), Some(0x3412));
+        let memory = memory_with(0xC000, &[0xFF; 5]);
+        assert_eq!(single("[title:X]\nbytes = C000-C004\nencoding = be").read(&memory), None);
+    }
+
+    #[test]
+    fn reads_digits() {
+        let memory = memory_with(0xC000, &[0x31, 0x32, 0x20, 0x39]);
+        let definition = single("[title:X]\nbytes = C000-C003\nencoding = digits\ndigit_base = 30");
+        assert_eq!(definition.read(&memory), Some(1209));
+        let definition = single("[title:X]\ndigit_base = 30\nbytes = C000-C003\nencoding = digits");
+        assert_eq!(definition.read(&memory), Some(1209));
+    }
+
+    #[test]
+    fn checks_conditions() {
+        let text = "[title:X]\nbytes = C000\nencoding = be\nvalid = C001&0F == 02\nvalid = C002 >= 10";
+        assert_eq!(single(text).read(&memory_with(0xC000, &[7, 0xF2, 0x10])), Some(7));
+        assert_eq!(single(text).read(&memory_with(0xC000, &[7, 0xF3, 0x10])), None);
+        assert_eq!(single(text).read(&memory_with(0xC000, &[7, 0x02, 0x0F])), None);
+        assert!(Condition::parse("C000 ==").is_none());
+        assert!(Condition::parse("C000&GG == 01").is_none());
+        assert!(Condition::parse("C000 == 100").is_none());
+    }
+}
@@ -0,0 +1,39 @@
+use crate::crc32::crc32;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+use std::fs;
+use std::io;
+
+// Writes the PPU's displaybuffer out as an 8 bit RGB PNG
+
+pub fn save_png(path: &str, displaybuffer: &[u8], width: usize, height: usize) -> io::Result<()> {
+    // Each scanline is prefixed by its filter type, 0 => none. The displaybuffer is B, G, R, unused.
+    let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+    for row in displaybuffer.chunks(width * 4).take(height) {
+        scanlines.push(0);
+        for pixel in row.chunks(4) {
+            scanlines.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // bit depth, colour type (RGB), compression, filter, interlace
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &compress_to_vec_zlib(&scanlines, 6));
+    write_chunk(&mut png, b"IEND", &[]);
+    fs::write(path, png)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
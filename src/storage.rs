@@ -0,0 +1,502 @@
+use postgres::{Client, NoTls};
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Accounts, scores and the games that have score tracking. The file backend is the default and needs
+// no setup; Postgres is used when .env sets STORAGE=postgres and DATABASE_URL.
+
+pub struct Account {
+    pub uuid: i32,
+    pub username: String,
+    pub password: String, // an argon2 PHC string, or plaintext in rows from before hashing, see accounts::migrate
+}
+
+// A row of a leaderboard
+pub struct ScoreEntry {
+    pub uuid: i32,
+    pub username: String,
+    pub title: String,
+    pub score: i32,
+}
+
+// One sitting with a game. started is in seconds since the unix epoch, duration in seconds.
+// title is filled in when reading sessions back.
+pub struct Session {
+    pub uuid: i32,
+    pub guid: i32,
+    pub title: String,
+    pub started: i64,
+    pub duration: i32,
+    pub final_score: i32,
+    pub peak_score: i32,
+}
+
+// An achievement a user has unlocked, unlocked_at in seconds since the unix epoch
+pub struct Unlock {
+    pub guid: i32,
+    pub id: String,
+    pub unlocked_at: i64,
+}
+
+pub trait Storage {
+    fn find_user(&mut self, username: &str) -> io::Result<Option<Account>>;
+    // Returns the new account's uuid
+    fn create_user(&mut self, username: &str, password: &str) -> io::Result<i32>;
+    fn users(&mut self) -> io::Result<Vec<Account>>;
+    fn set_password(&mut self, uuid: i32, password: &str) -> io::Result<()>;
+    // Removes the account and its scores
+    fn delete_user(&mut self, uuid: i32) -> io::Result<()>;
+    // The guid of a game with score tracking, looked up by its header title
+    fn game_guid(&mut self, title: &str) -> io::Result<Option<i32>>;
+    // Returns the new game's guid
+    fn add_game(&mut self, title: &str) -> io::Result<i32>;
+    // Every game with score tracking, by title
+    fn games(&mut self) -> io::Result<Vec<(i32, String)>>;
+    fn score(&mut self, uuid: i32, guid: i32) -> io::Result<Option<i32>>;
+    fn set_score(&mut self, uuid: i32, guid: i32, score: i32) -> io::Result<()>;
+    // Every user's score for one game, or for every game with None, highest first
+    fn leaderboard(&mut self, guid: Option<i32>) -> io::Result<Vec<ScoreEntry>>;
+    fn add_session(&mut self, session: &Session) -> io::Result<()>;
+    // A user's sessions, oldest first
+    fn sessions(&mut self, uuid: i32) -> io::Result<Vec<Session>>;
+    fn add_unlock(&mut self, uuid: i32, unlock: &Unlock) -> io::Result<()>;
+    // Every achievement a user has unlocked, in every game
+    fn unlocks(&mut self, uuid: i32) -> io::Result<Vec<Unlock>>;
+    // The crc32s of the roms a user has marked as favourites in the library
+    fn favourites(&mut self, uuid: i32) -> io::Result<Vec<u32>>;
+    fn set_favourite(&mut self, uuid: i32, rom_hash: u32, favourite: bool) -> io::Result<()>;
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/////////////////////////////// FILE ////////////////////////////////
+
+// One tab separated file per table in `dir`, rewritten whole on every change:
+//   users.tsv   uuid | username | password
+//   games.tsv   guid | title
+//   scores.tsv  uuid | guid | score
+//   sessions.tsv  uuid | guid | started | duration | final score | peak score, only ever appended to
+//   unlocks.tsv   uuid | guid | achievement id | unlocked at, also only appended to
+//   favourites.tsv  uuid | rom crc32
+// Every game is added the first time it's played, so its play time can be recorded.
+
+const DEFAULT_GAMES: [(i32, &str); 2] = [(2, "TETRIS"), (3, "DR.MARIO")];
+
+pub struct FileStorage {
+    dir: PathBuf,
+    users: Vec<Account>,
+    games: Vec<(i32, String)>,
+    scores: Vec<(i32, i32, i32)>,
+    sessions: Vec<Session>,
+    unlocks: Vec<(i32, Unlock)>,
+    favourites: Vec<(i32, u32)>,
+}
+
+impl FileStorage {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut storage = FileStorage {
+            dir: dir.to_path_buf(),
+            users: Vec::new(),
+            games: Vec::new(),
+            scores: Vec::new(),
+            sessions: Vec::new(),
+            unlocks: Vec::new(),
+            favourites: Vec::new(),
+        };
+
+        for fields in storage.read_table("users.tsv", 3)? {
+            storage.users.push(Account { uuid: parse(&fields[0])?, username: fields[1].clone(), password: fields[2].clone() });
+        }
+        if storage.dir.join("games.tsv").exists() {
+            for fields in storage.read_table("games.tsv", 2)? {
+                storage.games.push((parse(&fields[0])?, fields[1].clone()));
+            }
+        } else {
+            storage.games = DEFAULT_GAMES.iter().map(|(guid, title)| (*guid, title.to_string())).collect();
+            storage.save_games()?;
+        }
+        for fields in storage.read_table("scores.tsv", 3)? {
+            storage.scores.push((parse(&fields[0])?, parse(&fields[1])?, parse(&fields[2])?));
+        }
+        for fields in storage.read_table("sessions.tsv", 6)? {
+            storage.sessions.push(Session {
+                uuid: parse(&fields[0])?,
+                guid: parse(&fields[1])?,
+                title: String::new(),
+                started: fields[2].parse().map_err(|_| invalid(format!("expected a number, found {}", fields[2])))?,
+                duration: parse(&fields[3])?,
+                final_score: parse(&fields[4])?,
+                peak_score: parse(&fields[5])?,
+            });
+        }
+        for fields in storage.read_table("unlocks.tsv", 4)? {
+            let unlocked_at = fields[3].parse().map_err(|_| invalid(format!("expected a number, found {}", fields[3])))?;
+            storage.unlocks.push((parse(&fields[0])?, Unlock { guid: parse(&fields[1])?, id: fields[2].clone(), unlocked_at }));
+        }
+        for fields in storage.read_table("favourites.tsv", 2)? {
+            let rom_hash = fields[1].parse().map_err(|_| invalid(format!("expected a number, found {}", fields[1])))?;
+            storage.favourites.push((parse(&fields[0])?, rom_hash));
+        }
+        Ok(storage)
+    }
+
+    // A missing table is empty
+    fn read_table(&self, name: &str, columns: usize) -> io::Result<Vec<Vec<String>>> {
+        let path = self.dir.join(name);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut rows = Vec::new();
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+            let fields: Vec<String> = line.split('\t').map(|field| field.to_string()).collect();
+            if fields.len() != columns {
+                return Err(invalid(format!("{} line {}: expected {} fields", path.display(), number + 1, columns)));
+            }
+            rows.push(fields);
+        }
+        Ok(rows)
+    }
+
+    // Written to a temporary file first so a crash can't leave a table half written
+    fn write_table(&self, name: &str, rows: Vec<String>) -> io::Result<()> {
+        let path = self.dir.join(name);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, rows.iter().map(|row| row.clone() + "\n").collect::<String>())?;
+        fs::rename(temp, path)
+    }
+
+    fn save_users(&self) -> io::Result<()> {
+        self.write_table("users.tsv", self.users.iter().map(|user| format!("{}\t{}\t{}", user.uuid, user.username, user.password)).collect())
+    }
+
+    fn save_games(&self) -> io::Result<()> {
+        self.write_table("games.tsv", self.games.iter().map(|(guid, title)| format!("{}\t{}", guid, title)).collect())
+    }
+
+    fn save_scores(&self) -> io::Result<()> {
+        self.write_table("scores.tsv", self.scores.iter().map(|(uuid, guid, score)| format!("{}\t{}\t{}", uuid, guid, score)).collect())
+    }
+
+    fn save_favourites(&self) -> io::Result<()> {
+        self.write_table("favourites.tsv", self.favourites.iter().map(|(uuid, rom_hash)| format!("{}\t{}", uuid, rom_hash)).collect())
+    }
+}
+
+fn session_row(session: &Session) -> String {
+    format!("{}\t{}\t{}\t{}\t{}\t{}", session.uuid, session.guid, session.started, session.duration, session.final_score, session.peak_score)
+}
+
+fn unlock_row(uuid: i32, unlock: &Unlock) -> String {
+    format!("{}\t{}\t{}\t{}", uuid, unlock.guid, unlock.id, unlock.unlocked_at)
+}
+
+fn parse(field: &str) -> io::Result<i32> {
+    field.parse().map_err(|_| invalid(format!("expected a number, found {}", field)))
+}
+
+fn check_field(value: &str) -> io::Result<()> {
+    if value.contains('\t') || value.contains('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "tabs and newlines aren't allowed"));
+    }
+    Ok(())
+}
+
+impl Storage for FileStorage {
+    fn find_user(&mut self, username: &str) -> io::Result<Option<Account>> {
+        Ok(self.users.iter().find(|user| user.username == username).map(|user| Account {
+            uuid: user.uuid,
+            username: user.username.clone(),
+            password: user.password.clone(),
+        }))
+    }
+
+    fn create_user(&mut self, username: &str, password: &str) -> io::Result<i32> {
+        check_field(username)?;
+        check_field(password)?;
+        let uuid = self.users.iter().map(|user| user.uuid).max().unwrap_or(0) + 1;
+        self.users.push(Account { uuid, username: username.to_string(), password: password.to_string() });
+        self.save_users()?;
+        Ok(uuid)
+    }
+
+    fn users(&mut self) -> io::Result<Vec<Account>> {
+        Ok(self.users.iter().map(|user| Account {
+            uuid: user.uuid,
+            username: user.username.clone(),
+            password: user.password.clone(),
+        }).collect())
+    }
+
+    fn set_password(&mut self, uuid: i32, password: &str) -> io::Result<()> {
+        check_field(password)?;
+        match self.users.iter_mut().find(|user| user.uuid == uuid) {
+            Some(user) => user.password = password.to_string(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such account")),
+        }
+        self.save_users()
+    }
+
+    fn delete_user(&mut self, uuid: i32) -> io::Result<()> {
+        self.scores.retain(|(u, _, _)| *u != uuid);
+        self.save_scores()?;
+        self.sessions.retain(|session| session.uuid != uuid);
+        self.write_table("sessions.tsv", self.sessions.iter().map(session_row).collect())?;
+        self.unlocks.retain(|(u, _)| *u != uuid);
+        self.write_table("unlocks.tsv", self.unlocks.iter().map(|(uuid, unlock)| unlock_row(*uuid, unlock)).collect())?;
+        self.favourites.retain(|(u, _)| *u != uuid);
+        self.save_favourites()?;
+        self.users.retain(|user| user.uuid != uuid);
+        self.save_users()
+    }
+
+    fn game_guid(&mut self, title: &str) -> io::Result<Option<i32>> {
+        Ok(self.games.iter().find(|(_, name)| name == title).map(|(guid, _)| *guid))
+    }
+
+    fn add_game(&mut self, title: &str) -> io::Result<i32> {
+        check_field(title)?;
+        let guid = self.games.iter().map(|(guid, _)| *guid).max().unwrap_or(1) + 1;
+        self.games.push((guid, title.to_string()));
+        self.save_games()?;
+        Ok(guid)
+    }
+
+    fn games(&mut self) -> io::Result<Vec<(i32, String)>> {
+        let mut games = self.games.clone();
+        games.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(games)
+    }
+
+    fn score(&mut self, uuid: i32, guid: i32) -> io::Result<Option<i32>> {
+        Ok(self.scores.iter().find(|(u, g, _)| *u == uuid && *g == guid).map(|(_, _, score)| *score))
+    }
+
+    fn set_score(&mut self, uuid: i32, guid: i32, score: i32) -> io::Result<()> {
+        match self.scores.iter_mut().find(|(u, g, _)| *u == uuid && *g == guid) {
+            Some(row) => row.2 = score,
+            None => self.scores.push((uuid, guid, score)),
+        }
+        self.save_scores()
+    }
+
+    fn leaderboard(&mut self, guid: Option<i32>) -> io::Result<Vec<ScoreEntry>> {
+        let mut entries: Vec<ScoreEntry> = self.scores.iter()
+            .filter(|(_, g, _)| guid.map_or(true, |guid| *g == guid))
+            .filter_map(|(uuid, g, score)| {
+                let user = self.users.iter().find(|user| user.uuid == *uuid)?;
+                let (_, title) = self.games.iter().find(|(guid, _)| guid == g)?;
+                Some(ScoreEntry { uuid: *uuid, username: user.username.clone(), title: title.clone(), score: *score })
+            })
+            .collect();
+        entries.sort_by(|a, b| b.score.cmp(&a.score));
+        Ok(entries)
+    }
+
+    fn add_session(&mut self, session: &Session) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(self.dir.join("sessions.tsv"))?;
+        writeln!(file, "{}", session_row(session))?;
+        self.sessions.push(Session { title: String::new(), ..*session });
+        Ok(())
+    }
+
+    fn sessions(&mut self, uuid: i32) -> io::Result<Vec<Session>> {
+        Ok(self.sessions.iter().filter(|session| session.uuid == uuid).map(|session| Session {
+            title: self.games.iter().find(|(guid, _)| *guid == session.guid).map(|(_, title)| title.clone()).unwrap_or_default(),
+            ..*session
+        }).collect())
+    }
+
+    fn add_unlock(&mut self, uuid: i32, unlock: &Unlock) -> io::Result<()> {
+        check_field(&unlock.id)?;
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(self.dir.join("unlocks.tsv"))?;
+        writeln!(file, "{}", unlock_row(uuid, unlock))?;
+        self.unlocks.push((uuid, Unlock { guid: unlock.guid, id: unlock.id.clone(), unlocked_at: unlock.unlocked_at }));
+        Ok(())
+    }
+
+    fn unlocks(&mut self, uuid: i32) -> io::Result<Vec<Unlock>> {
+        Ok(self.unlocks.iter().filter(|(u, _)| *u == uuid)
+            .map(|(_, unlock)| Unlock { guid: unlock.guid, id: unlock.id.clone(), unlocked_at: unlock.unlocked_at }).collect())
+    }
+
+    fn favourites(&mut self, uuid: i32) -> io::Result<Vec<u32>> {
+        Ok(self.favourites.iter().filter(|(u, _)| *u == uuid).map(|(_, rom_hash)| *rom_hash).collect())
+    }
+
+    fn set_favourite(&mut self, uuid: i32, rom_hash: u32, favourite: bool) -> io::Result<()> {
+        self.favourites.retain(|row| *row != (uuid, rom_hash));
+        if favourite {
+            self.favourites.push((uuid, rom_hash));
+        }
+        self.save_favourites()
+    }
+}
+
+/////////////////////////////// POSTGRES ////////////////////////////////
+
+// Expects the users (uuid, username, password), games (guid, name) and scores (uuid, guid, score) tables.
+// The tables added since then are created on connect when they're missing, so older databases keep working.
+// users.password has to fit an argon2 hash, around 100 characters.
+pub struct PostgresStorage {
+    client: Client,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (uuid INT NOT NULL, guid INT NOT NULL, started BIGINT NOT NULL, duration INT NOT NULL,
+                                         final_score INT NOT NULL, peak_score INT NOT NULL);
+    CREATE TABLE IF NOT EXISTS unlocks (uuid INT NOT NULL, guid INT NOT NULL, id TEXT NOT NULL, unlocked_at BIGINT NOT NULL);
+    CREATE TABLE IF NOT EXISTS favourites (uuid INT NOT NULL, rom_hash BIGINT NOT NULL);
+";
+
+impl PostgresStorage {
+    pub fn connect(url: &str) -> io::Result<Self> {
+        let mut client = Client::connect(url, NoTls).map_err(database_error)?;
+        client.batch_execute(SCHEMA).map_err(database_error)?;
+        Ok(PostgresStorage { client })
+    }
+}
+
+fn database_error(e: postgres::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl Storage for PostgresStorage {
+    fn find_user(&mut self, username: &str) -> io::Result<Option<Account>> {
+        let rows = self.client.query("SELECT uuid, username, password FROM users WHERE username = $1", &[&username]).map_err(database_error)?;
+        Ok(rows.first().map(|row| Account { uuid: row.get(0), username: row.get(1), password: row.get(2) }))
+    }
+
+    fn create_user(&mut self, username: &str, password: &str) -> io::Result<i32> {
+        let row = self.client.query_one("INSERT INTO users (username, password) VALUES ($1, $2) RETURNING uuid",
+                                        &[&username, &password]).map_err(database_error)?;
+        Ok(row.get(0))
+    }
+
+    fn users(&mut self) -> io::Result<Vec<Account>> {
+        let rows = self.client.query("SELECT uuid, username, password FROM users", &[]).map_err(database_error)?;
+        Ok(rows.iter().map(|row| Account { uuid: row.get(0), username: row.get(1), password: row.get(2) }).collect())
+    }
+
+    fn set_password(&mut self, uuid: i32, password: &str) -> io::Result<()> {
+        self.client.execute("UPDATE users SET password = $1 WHERE uuid = $2", &[&password, &uuid]).map_err(database_error)?;
+        Ok(())
+    }
+
+    fn delete_user(&mut self, uuid: i32) -> io::Result<()> {
+        let mut transaction = self.client.transaction().map_err(database_error)?;
+        transaction.execute("DELETE FROM sessions WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        transaction.execute("DELETE FROM unlocks WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        transaction.execute("DELETE FROM favourites WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        transaction.execute("DELETE FROM scores WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        transaction.execute("DELETE FROM users WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        transaction.commit().map_err(database_error)
+    }
+
+    fn game_guid(&mut self, title: &str) -> io::Result<Option<i32>> {
+        let rows = self.client.query("SELECT guid FROM games WHERE name = $1", &[&title]).map_err(database_error)?;
+        Ok(rows.first().map(|row| row.get(0)))
+    }
+
+    fn add_game(&mut self, title: &str) -> io::Result<i32> {
+        let row = self.client.query_one("INSERT INTO games (name) VALUES ($1) RETURNING guid", &[&title]).map_err(database_error)?;
+        Ok(row.get(0))
+    }
+
+    fn games(&mut self) -> io::Result<Vec<(i32, String)>> {
+        let rows = self.client.query("SELECT guid, name FROM games ORDER BY name", &[]).map_err(database_error)?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn score(&mut self, uuid: i32, guid: i32) -> io::Result<Option<i32>> {
+        let rows = self.client.query("SELECT score FROM scores WHERE uuid = $1 AND guid = $2", &[&uuid, &guid]).map_err(database_error)?;
+        Ok(rows.first().map(|row| row.get(0)))
+    }
+
+    fn set_score(&mut self, uuid: i32, guid: i32, score: i32) -> io::Result<()> {
+        let updated = self.client.execute("UPDATE scores SET score = $1 WHERE uuid = $2 AND guid = $3",
+                                          &[&score, &uuid, &guid]).map_err(database_error)?;
+        if updated == 0 {
+            self.client.execute("INSERT INTO scores (uuid, guid, score) VALUES ($1, $2, $3)",
+                                &[&uuid, &guid, &score]).map_err(database_error)?;
+        }
+        Ok(())
+    }
+
+    fn leaderboard(&mut self, guid: Option<i32>) -> io::Result<Vec<ScoreEntry>> {
+        let rows = self.client.query("SELECT users.uuid, users.username, games.name, scores.score FROM scores
+                                      JOIN users ON users.uuid = scores.uuid JOIN games ON games.guid = scores.guid
+                                      WHERE $1::INT IS NULL OR scores.guid = $1 ORDER BY scores.score DESC",
+                                     &[&guid]).map_err(database_error)?;
+        Ok(rows.iter().map(|row| ScoreEntry { uuid: row.get(0), username: row.get(1), title: row.get(2), score: row.get(3) }).collect())
+    }
+    fn add_session(&mut self, session: &Session) -> io::Result<()> {
+        self.client.execute("INSERT INTO sessions (uuid, guid, started, duration, final_score, peak_score) VALUES ($1, $2, $3, $4, $5, $6)",
+                            &[&session.uuid, &session.guid, &session.started, &session.duration, &session.final_score, &session.peak_score])
+                            .map_err(database_error)?;
+        Ok(())
+    }
+
+    fn sessions(&mut self, uuid: i32) -> io::Result<Vec<Session>> {
+        let rows = self.client.query("SELECT sessions.guid, games.name, started, duration, final_score, peak_score FROM sessions
+                                      JOIN games ON games.guid = sessions.guid WHERE uuid = $1 ORDER BY started",
+                                     &[&uuid]).map_err(database_error)?;
+        Ok(rows.iter().map(|row| Session {
+            uuid,
+            guid: row.get(0),
+            title: row.get(1),
+            started: row.get(2),
+            duration: row.get(3),
+            final_score: row.get(4),
+            peak_score: row.get(5),
+        }).collect())
+    }
+    fn add_unlock(&mut self, uuid: i32, unlock: &Unlock) -> io::Result<()> {
+        self.client.execute("INSERT INTO unlocks (uuid, guid, id, unlocked_at) VALUES ($1, $2, $3, $4)",
+                            &[&uuid, &unlock.guid, &unlock.id, &unlock.unlocked_at]).map_err(database_error)?;
+        Ok(())
+    }
+
+    fn unlocks(&mut self, uuid: i32) -> io::Result<Vec<Unlock>> {
+        let rows = self.client.query("SELECT guid, id, unlocked_at FROM unlocks WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        Ok(rows.iter().map(|row| Unlock { guid: row.get(0), id: row.get(1), unlocked_at: row.get(2) }).collect())
+    }
+
+    // rom_hash is a BIGINT since Postgres has no unsigned 32 bit type
+    fn favourites(&mut self, uuid: i32) -> io::Result<Vec<u32>> {
+        let rows = self.client.query("SELECT rom_hash FROM favourites WHERE uuid = $1", &[&uuid]).map_err(database_error)?;
+        Ok(rows.iter().map(|row| row.get::<_, i64>(0) as u32).collect())
+    }
+
+    fn set_favourite(&mut self, uuid: i32, rom_hash: u32, favourite: bool) -> io::Result<()> {
+        let rom_hash = rom_hash as i64;
+        self.client.execute("DELETE FROM favourites WHERE uuid = $1 AND rom_hash = $2", &[&uuid, &rom_hash]).map_err(database_error)?;
+        if favourite {
+            self.client.execute("INSERT INTO favourites (uuid, rom_hash) VALUES ($1, $2)", &[&uuid, &rom_hash]).map_err(database_error)?;
+        }
+        Ok(())
+    }
+}
+
+// From .env: STORAGE=file (default, in DATA_DIR, default ./data) or STORAGE=postgres (DATABASE_URL)
+pub fn from_env() -> io::Result<Box<dyn Storage>> {
+    let kind = std::env::var("STORAGE").unwrap_or_else(|_| String::from("file"));
+    match kind.as_str() {
+        "file" => {
+            let dir = std::env::var("DATA_DIR").unwrap_or_else(|_| String::from("data"));
+            Ok(Box::new(FileStorage::open(Path::new(&dir))?))
+        },
+        "postgres" => match std::env::var("DATABASE_URL") {
+            Ok(url) => Ok(Box::new(PostgresStorage::connect(&url)?)),
+            Err(_) => Err(io::Error::new(io::ErrorKind::NotFound, "STORAGE=postgres needs DATABASE_URL")),
+        },
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown STORAGE: {}", kind))),
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// RGBDS / no$gmb symbol files. One symbol per line, "BB:AAAA Name", with ';' comments:
+//   ; File generated by rgblink
+//   00:0150 Main
+//   00:0155 Main.loop
+//   01:4000 LevelData
+pub struct SymbolTable {
+    by_address: HashMap<(u16, u16), String>,
+    by_name: HashMap<String, (u16, u16)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            by_address: HashMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut table = SymbolTable::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let mut fields = line.split_whitespace();
+            let (location, name) = match (fields.next(), fields.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => continue,
+            };
+            if let Some((bank, address)) = parse_location(location) {
+                table.insert(bank, address, name);
+            }
+        }
+        Ok(table)
+    }
+
+    // Looks for "<rom>.sym" next to the rom, returning an empty table if there isn't one
+    pub fn for_rom(rom_path: &str) -> Self {
+        let path = Path::new(rom_path).with_extension("sym");
+        if !path.exists() {
+            return SymbolTable::new();
+        }
+        match SymbolTable::load(&path) {
+            Ok(table) => {
+                println!("LOADED {} SYMBOLS FROM {}", table.by_name.len(), path.display());
+                table
+            },
+            Err(e) => {
+                println!("UNABLE TO READ {}: {}", path.display(), e);
+                SymbolTable::new()
+            },
+        }
+    }
+
+    pub fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        // Keep the first name at an address - rgblink lists the outer label before its locals
+        self.by_address.entry((bank, address)).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), (bank, address));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        self.by_address.get(&(bank, address)).map(|name| name.as_str())
+    }
+
+    // Label for an address as currently mapped into the cpu's address space
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.label(mapped_bank(address), address)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<(u16, u16)> {
+        self.by_name.get(name).copied()
+    }
+
+    // Resolves a breakpoint style argument: a label, "BB:AAAA", "$AAAA", "0xAAAA" or bare hex
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        if let Some((_, address)) = self.lookup(text) {
+            return Some(address);
+        }
+        if let Some((_, address)) = parse_location(text) {
+            return Some(address);
+        }
+        let digits = text.trim_start_matches('$').trim_start_matches("0x");
+        u16::from_str_radix(digits, 16).ok()
+    }
+
+    // "BB:AAAA" or, with a symbol, "BB:AAAA (Name)"
+    pub fn describe(&self, address: u16) -> String {
+        let bank = mapped_bank(address);
+        match self.label(bank, address) {
+            Some(name) => format!("{:02X}:{:04X} ({})", bank, address, name),
+            None => format!("{:02X}:{:04X}", bank, address),
+        }
+    }
+}
+
+fn parse_location(text: &str) -> Option<(u16, u16)> {
+    let (bank, address) = text.split_once(':')?;
+    let bank = u16::from_str_radix(bank, 16).ok()?;
+    let address = u16::from_str_radix(address, 16).ok()?;
+    Some((bank, address))
+}
+
+// Only 32KiB of rom is mapped (no MBC yet), so the switchable regions are always bank 1
+pub fn mapped_bank(address: u16) -> u16 {
+    match address {
+        0x4000..=0x7FFF => 1,
+        0xD000..=0xDFFF => 1,
+        _ => 0,
+    }
+}
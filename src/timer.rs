@@ -1,3 +1,5 @@
+use crate::savestate::{StateReader, StateWriter};
+
 // Code for managing timer registers etc.
 pub struct Timer {
     sysclk: u16,
@@ -106,4 +108,26 @@ impl Timer {
             _ => unreachable!(),
         }
     }
-}
\ No newline at end of file
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.sysclk);
+        state.u8(self.tima);
+        state.u8(self.tma);
+        state.u8(self.tac);
+        state.u8(self.last_bit);
+        state.bool(self.tima_reload_cycle);
+        state.u8(self.tima_cycles_to_irq);
+        state.bool(self.tima_overflow_irq);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) {
+        self.sysclk = state.u16();
+        self.tima = state.u8();
+        self.tma = state.u8();
+        self.tac = state.u8();
+        self.last_bit = state.u8();
+        self.tima_reload_cycle = state.bool();
+        self.tima_cycles_to_irq = state.u8();
+        self.tima_overflow_irq = state.bool();
+    }
+}
@@ -0,0 +1,293 @@
+use crate::memory::Memory;
+use crate::palette::Palette;
+use crate::ppu::Sprite;
+
+use sdl2::{
+    event::{Event, WindowEvent},
+    gfx::primitives::DrawRenderer,
+    pixels::{Color, PixelFormatEnum},
+    render::{Canvas, Texture},
+    video::Window,
+    VideoSubsystem,
+};
+
+// Debug windows that sit next to the main SDLRenderer window. Each one is opened / closed with a
+// hotkey (T => tile maps, Y => tile data, U => OAM) and redrawn from memory once per frame.
+
+const PIXELSIZE: usize = 4;
+
+const TILES_PER_ROW: u32 = 16;
+const TILE_COUNT: u32 = 384;
+const MAP_SIZE: u32 = 256;
+const MAP_GAP: u32 = 8;
+
+const OUTLINE: [u8; 3] = [0, 0, 255]; // drawn in displaybuffer byte order, so this is red
+
+/////////////////////////////// WINDOWS ////////////////////////////////
+
+struct PixelWindow {
+    width: u32,
+    canvas: Canvas<Window>,
+    texture: Texture,
+    buffer: Vec<u8>,
+}
+
+impl PixelWindow {
+    fn new(video_subsystem: &VideoSubsystem, title: &str, width: u32, height: u32, scale: u32) -> Self {
+        let window = video_subsystem.window(title, width * scale, height * scale)
+        .build()
+        .expect("failed to build debug window");
+
+        let canvas = window.into_canvas()
+        .build()
+        .expect("failed to build debug window's canvas");
+
+        let texture = canvas.texture_creator()
+        .create_texture_streaming(PixelFormatEnum::RGB888, width, height)
+        .expect("failed to create debug texture");
+
+        PixelWindow {
+            width,
+            canvas,
+            texture,
+            buffer: vec![0; (width * height) as usize * PIXELSIZE],
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+        let index = ((y * self.width + x) as usize) * PIXELSIZE;
+        self.buffer[index..index + 3].copy_from_slice(&rgb);
+    }
+
+    fn present(&mut self) {
+        self.texture
+            .update(None, &self.buffer, self.width as usize * PIXELSIZE)
+            .expect("failed to update debug texture");
+        self.canvas.copy(&self.texture, None, None).expect("failed to copy debug texture");
+        self.canvas.present();
+    }
+}
+
+// Reads the 2 bit colour of pixel (x, y) of the tile whose 16 bytes start at address
+fn tile_pixel(memory: &Memory, address: u16, x: u8, y: u8) -> u8 {
+    let low = memory.read(address + (y as u16) * 2);
+    let high = memory.read(address + (y as u16) * 2 + 1);
+    let bit = 7 - x;
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
+
+fn apply_palette(memory: &Memory, palette_register: u16, colour_id: u8) -> u8 {
+    (memory.read(palette_register) >> (colour_id * 2)) & 0b11
+}
+
+/////////////////////////////// TILE DATA ////////////////////////////////
+
+// All 384 tiles in 8000 -> 97FF, 16 to a row, drawn with BGP
+pub struct TileViewer {
+    window: PixelWindow,
+}
+
+impl TileViewer {
+    pub fn new(video_subsystem: &VideoSubsystem) -> Self {
+        let rows = TILE_COUNT / TILES_PER_ROW;
+        TileViewer {
+            window: PixelWindow::new(video_subsystem, "Tile Data", TILES_PER_ROW * 8, rows * 8, 3),
+        }
+    }
+
+    pub fn update(&mut self, memory: &Memory, palette: &Palette) {
+        for tile in 0..TILE_COUNT {
+            let address = 0x8000 + (tile as u16) * 16;
+            let tile_x = (tile % TILES_PER_ROW) * 8;
+            let tile_y = (tile / TILES_PER_ROW) * 8;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let colour = apply_palette(memory, 0xFF47, tile_pixel(memory, address, x, y));
+                    self.window.set_pixel(tile_x + x as u32, tile_y + y as u32, palette.rgb(colour));
+                }
+            }
+        }
+        self.window.present();
+    }
+}
+
+/////////////////////////////// TILE MAPS ////////////////////////////////
+
+// Both 32x32 background maps (9800 on the left, 9C00 on the right) using the tile data area
+// currently selected in LCDC. The SCX/SCY viewport is outlined on the map the background uses.
+pub struct MapViewer {
+    window: PixelWindow,
+}
+
+impl MapViewer {
+    pub fn new(video_subsystem: &VideoSubsystem) -> Self {
+        MapViewer {
+            window: PixelWindow::new(video_subsystem, "Tile Maps", MAP_SIZE * 2 + MAP_GAP, MAP_SIZE, 2),
+        }
+    }
+
+    pub fn update(&mut self, memory: &Memory, palette: &Palette) {
+        let lcdc = memory.read(0xFF40);
+        for (map_index, map) in [0x9800_u16, 0x9C00].iter().enumerate() {
+            let origin_x = map_index as u32 * (MAP_SIZE + MAP_GAP);
+            for tile_y in 0..32 {
+                for tile_x in 0..32 {
+                    let tile_number = memory.read(map + tile_y * 32 + tile_x);
+                    let address = if lcdc & 0b0001_0000 == 0 && tile_number < 128 {
+                        0x9000 + (tile_number as u16) * 16
+                    } else { 0x8000 + (tile_number as u16) * 16 };
+                    for y in 0..8 {
+                        for x in 0..8 {
+                            let colour = apply_palette(memory, 0xFF47, tile_pixel(memory, address, x, y));
+                            self.window.set_pixel(
+                                origin_x + tile_x as u32 * 8 + x as u32,
+                                tile_y as u32 * 8 + y as u32,
+                                palette.rgb(colour),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // Viewport outline, wrapping around the map edges like the hardware does
+        let origin_x = if lcdc & 0b0000_1000 == 0 { 0 } else { MAP_SIZE + MAP_GAP };
+        let scy = memory.read(0xFF42) as u32;
+        let scx = memory.read(0xFF43) as u32;
+        for i in 0..160 {
+            let x = (scx + i) % MAP_SIZE;
+            self.window.set_pixel(origin_x + x, scy, OUTLINE);
+            self.window.set_pixel(origin_x + x, (scy + 143) % MAP_SIZE, OUTLINE);
+        }
+        for i in 0..144 {
+            let y = (scy + i) % MAP_SIZE;
+            self.window.set_pixel(origin_x + scx, y, OUTLINE);
+            self.window.set_pixel(origin_x + (scx + 159) % MAP_SIZE, y, OUTLINE);
+        }
+        self.window.present();
+    }
+}
+
+/////////////////////////////// OAM ////////////////////////////////
+
+// Lists all 40 OAM entries decoded as Sprites
+pub struct OamViewer {
+    canvas: Canvas<Window>,
+}
+
+impl OamViewer {
+    const LINE_HEIGHT: i16 = 10;
+
+    pub fn new(video_subsystem: &VideoSubsystem) -> Self {
+        let window = video_subsystem.window("OAM", 400, (42 * Self::LINE_HEIGHT + 8) as u32)
+        .build()
+        .expect("failed to build debug window");
+
+        OamViewer {
+            canvas: window.into_canvas().build().expect("failed to build debug window's canvas"),
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn update(&mut self, memory: &Memory) {
+        let text_colour = Color::RGB(255, 255, 255);
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.string(4, 4, " #   Y   X  TILE  PRI YFLIP XFLIP PAL", text_colour).ok();
+        for entry in 0..40 {
+            let base = entry * 4;
+            let sprite = Sprite::new(memory.oam[base], memory.oam[base + 1], memory.oam[base + 2], memory.oam[base + 3]);
+            let line = format!(
+                "{:2} {:3} {:3}   {:02X}    {}    {}     {}    OBP{}",
+                entry,
+                sprite.y,
+                sprite.x,
+                sprite.index,
+                (sprite.attributes >> 7) & 1,
+                (sprite.attributes >> 6) & 1,
+                (sprite.attributes >> 5) & 1,
+                (sprite.attributes >> 4) & 1,
+            );
+            // Sprites with y of 0 or >= 160 are off screen, so dim them
+            let colour = if sprite.y == 0 || sprite.y >= 160 { Color::RGB(110, 110, 110) } else { text_colour };
+            self.canvas.string(4, 4 + (entry as i16 + 2) * Self::LINE_HEIGHT, &line, colour).ok();
+        }
+        self.canvas.present();
+    }
+}
+
+/////////////////////////////// VIEWERS ////////////////////////////////
+
+pub struct Viewers {
+    pub tiles: Option<TileViewer>,
+    pub maps: Option<MapViewer>,
+    pub oam: Option<OamViewer>,
+}
+
+impl Viewers {
+    pub fn new() -> Self {
+        Viewers {
+            tiles: None,
+            maps: None,
+            oam: None,
+        }
+    }
+
+    pub fn toggle_tiles(&mut self, video_subsystem: &VideoSubsystem) {
+        self.tiles = match self.tiles {
+            Some(_) => None,
+            None => Some(TileViewer::new(video_subsystem)),
+        };
+    }
+
+    pub fn toggle_maps(&mut self, video_subsystem: &VideoSubsystem) {
+        self.maps = match self.maps {
+            Some(_) => None,
+            None => Some(MapViewer::new(video_subsystem)),
+        };
+    }
+
+    pub fn toggle_oam(&mut self, video_subsystem: &VideoSubsystem) {
+        self.oam = match self.oam {
+            Some(_) => None,
+            None => Some(OamViewer::new(video_subsystem)),
+        };
+    }
+
+    // Closes a debug window if the event is its close button. Returns true if the event was consumed.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let window_id = match event {
+            Event::Window { win_event: WindowEvent::Close, window_id, .. } => *window_id,
+            _ => return false,
+        };
+        if self.tiles.as_ref().map(|v| v.window.id()) == Some(window_id) {
+            self.tiles = None;
+        } else if self.maps.as_ref().map(|v| v.window.id()) == Some(window_id) {
+            self.maps = None;
+        } else if self.oam.as_ref().map(|v| v.id()) == Some(window_id) {
+            self.oam = None;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn update(&mut self, memory: &Memory, palette: &Palette) {
+        if let Some(tiles) = self.tiles.as_mut() {
+            tiles.update(memory, palette);
+        }
+        if let Some(maps) = self.maps.as_mut() {
+            maps.update(memory, palette);
+        }
+        if let Some(oam) = self.oam.as_mut() {
+            oam.update(memory);
+        }
+    }
+}